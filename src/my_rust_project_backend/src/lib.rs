@@ -1,8 +1,15 @@
-use candid::{CandidType, Deserialize, Principal};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument,
+    SignWithEcdsaArgument,
+};
+use ic_cdk::api::management_canister::main::raw_rand;
 use ic_cdk::api::time;
-use ic_cdk_macros::{init, query, update};
-use std::collections::{BTreeMap, HashMap};
+use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::cell::RefCell;
+use std::time::Duration;
 
 // Types and Structs
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -11,26 +18,316 @@ pub struct Event {
     pub name: String,
     pub description: String,
     pub venue: String,
+    pub category: String,
+    pub image_url: String,
+    pub metadata_url: Option<String>,
+    pub notify_canister: Option<Principal>, // Told about every check-in via TICKET_USED_CALLBACK
+    pub series_id: Option<u64>, // Shared by every occurrence of a recurring event
     pub date: u64, // Unix timestamp
+    pub previous_date: Option<u64>, // Date before the most recent reschedule
+    pub rescheduled_at: Option<u64>,
     pub total_tickets: u32,
     pub available_tickets: u32,
-    pub price_icp: u64, // Price in e8s (1 ICP = 100,000,000 e8s)
+    pub reserved_tickets: u32, // Part of available_tickets withheld for organizer comps
+    pub low_stock_threshold: Option<u32>, // Remaining tickets at which the event counts as low on stock
+    pub price_icp: u64, // Price in the smallest unit of payment_token (e8s for ICP)
+    pub price_schedule: Vec<PriceStep>, // Time-based prices for the default tier; empty for a fixed price
+    pub display_price: Option<(String, u64)>, // Informational fiat price (ISO 4217 code, amount); never charged
+    pub payment_token: PaymentToken,
+    pub tiers: Vec<TicketTier>,
+    pub seat_map: Vec<String>, // Seat labels buyers pick from; empty if seats are assigned
+    pub seat_numbering: SeatNumbering, // How seats are assigned when there is no seat map
+    pub custom_fields: Vec<String>, // Attendee details every buyer fills in, e.g. "T-shirt size"
     pub organizer: Principal,
+    pub staff: Vec<Principal>,
+    pub co_organizers: Vec<(Principal, OrganizerRole)>, // Team members sharing the organizer's controls
     pub max_tickets_per_user: u32,
+    pub min_tickets_per_purchase: u32,
+    pub max_tickets_per_purchase: u32,
+    pub presale_start_time: Option<u64>, // Allowlisted buyers may buy from here until sale_start_time
     pub sale_start_time: u64,
     pub sale_end_time: u64,
+    pub auto_extend: Option<u64>, // Nanoseconds the sale is extended by while tickets are left, up to the event date
+    pub auto_extend_max: Option<u64>, // Cap on the total extension past the scheduled sale end; only the event date if None
+    pub auto_extended: u64, // How far `sale_end_time` has been pushed past its scheduled end so far
+    pub refund_deadline: u64,
+    pub check_in_window: Option<u64>, // How long after `date` tickets can still be scanned; no limit if None
+    pub requires_verified_buyers: bool,
+    pub min_reputation: Option<u32>, // Buyers below this reputation score are turned away; no gate if None
+    pub allowlist: Option<Vec<Principal>>, // Only these principals may buy; public sale if None or empty
+    pub transferable: bool, // Will-call events lock tickets to their original buyer
+    pub resale_cap_bps: Option<u32>, // Resale ceiling as a share of what the ticket cost; what it cost if None
+    pub refunds_require_approval: bool, // Refunds go through `request_refund` instead of `refund_ticket`
     pub is_active: bool,
+    pub cancelled: bool,
+    pub completed: bool, // Set once the event date has passed
+    pub no_shows_penalized: bool, // Set once unused tickets have cost their holders reputation
+    pub archived: bool, // Hidden from the listing queries once finished
+    pub review_count: u32,
+    pub average_rating: Option<f64>,
+    pub status: EventStatus, // Derived from the timestamps and flags above whenever the event is read
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum EventStatus {
+    #[default]
+    Upcoming,
+    OnSale,
+    SaleEnded,
+    Live,
+    Completed,
+    Cancelled,
+}
+
+// Token an event is priced and paid in. Every amount tied to the event (tier prices,
+// purchase totals, refunds, resale prices) is in this token's smallest unit.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum PaymentToken {
+    #[default]
+    ICP,
+    CkUSDC,
+}
+
+impl PaymentToken {
+    fn ledger_canister(self) -> Principal {
+        let canister_id = match self {
+            PaymentToken::ICP => ICP_LEDGER_CANISTER_ID,
+            PaymentToken::CkUSDC => CKUSDC_LEDGER_CANISTER_ID,
+        };
+        Principal::from_text(canister_id).expect("Invalid ledger canister id")
+    }
+
+    // Fee the ledger charges the sender for each transfer
+    fn ledger_fee(self) -> u64 {
+        match self {
+            PaymentToken::ICP => ICP_LEDGER_FEE,
+            PaymentToken::CkUSDC => CKUSDC_LEDGER_FEE,
+        }
+    }
+}
+
+// Badge-ready view of how many tickets are left on public sale
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InventoryStatus {
+    Available,
+    LowStock { remaining: u32 },
+    SoldOut,
+}
+
+// What a co-organizer may do on the organizer's behalf. Sales proceeds still accrue to,
+// and are only withdrawn by, the organizer.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrganizerRole {
+    FullAdmin, // Everything the organizer can do except handing the event over
+    ScannerManager, // Check tickets in and manage gate staff
+    FinanceOnly, // Review refund requests and flag chargebacks
+}
+
+// How a principal is attached to an event they can manage
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventAccess {
+    Organizer,
+    CoOrganizer(OrganizerRole),
+    Staff,
+}
+
+// Labels given to tickets of events without a seat map, in the order they're sold
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SeatNumbering {
+    #[default]
+    Sequential, // 1, 2, 3, ...
+    RowCol { rows: u32, cols: u32 }, // A1, A2, ..., B1, ...
+    None, // General admission; tickets carry no seat
+}
+
+// Full configuration for a new event, as taken by `create_event_with` and `create_event_series`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EventInput {
+    pub name: String,
+    pub description: String,
+    pub venue: String,
+    pub date: u64,
+    pub total_tickets: u32,
+    pub price_icp: u64,
+    pub max_tickets_per_user: u32,
+    pub presale_start_time: Option<u64>,
+    pub sale_start_time: u64,
+    pub sale_end_time: u64,
+    pub auto_extend: Option<u64>,
+    pub auto_extend_max: Option<u64>,
+    pub refund_deadline: Option<u64>,
+    pub tiers: Option<Vec<TicketTier>>,
+    pub category: Option<String>,
+    pub requires_verified_buyers: Option<bool>,
+    pub min_reputation: Option<u32>,
+    pub seat_map: Option<Vec<String>>,
+    pub seat_numbering: Option<SeatNumbering>,
+    pub custom_fields: Option<Vec<String>>,
+    pub allowlist: Option<Vec<Principal>>,
+    pub price_schedule: Option<Vec<PriceStep>>,
+    pub transferable: Option<bool>,
+    pub image_url: Option<String>,
+    pub metadata_url: Option<String>,
+    pub allow_venue_overlap: Option<bool>, // Skip the double-booking check, for venues that host parallel events
+    pub notify_canister: Option<Principal>,
+}
+
+// Artwork and off-chain metadata links, grouped so `create_event` stays within Candid's
+// 16-argument limit
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct EventMedia {
+    pub image_url: Option<String>,
+    pub metadata_url: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TicketTier {
+    pub name: String,
+    pub price_icp: u64,
+    pub total: u32,
+    pub available: u32,
+}
+
+// From `effective_from` on, the default tier sells at `price_icp` until the next step
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PriceStep {
+    pub effective_from: u64,
+    pub price_icp: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TierStatistics {
+    pub name: String,
+    pub sold: u32,
+    pub available: u32,
+    pub revenue: u64,
+}
+
+impl Event {
+    fn tier(&self, name: &str) -> Option<&TicketTier> {
+        self.tiers.iter().find(|tier| tier.name == name)
+    }
+
+    fn tier_mut(&mut self, name: &str) -> Option<&mut TicketTier> {
+        self.tiers.iter_mut().find(|tier| tier.name == name)
+    }
+
+    // The latest price step already in effect, if the event has a schedule that has started
+    fn scheduled_price(&self, current_time: u64) -> Option<u64> {
+        self.price_schedule.iter()
+            .rev()
+            .find(|step| step.effective_from <= current_time)
+            .map(|step| step.price_icp)
+    }
+
+    // The price schedule only applies to the default (first) tier
+    fn tier_price(&self, tier: &TicketTier, current_time: u64) -> u64 {
+        let is_default = self.tiers.first().is_some_and(|first| first.name == tier.name);
+        match self.scheduled_price(current_time) {
+            Some(price_icp) if is_default => price_icp,
+            _ => tier.price_icp,
+        }
+    }
+
+    // Most `ticket` may be listed for resale at: what it was bought for, scaled by any
+    // organizer-set cap
+    fn resale_cap(&self, ticket: &Ticket) -> u64 {
+        let cap_bps = self.resale_cap_bps.unwrap_or(10_000);
+        u64::try_from(ticket.price_paid as u128 * cap_bps as u128 / 10_000).unwrap_or(u64::MAX)
+    }
+
+    fn co_organizer_role(&self, principal: Principal) -> Option<OrganizerRole> {
+        self.co_organizers.iter()
+            .find(|(member, _)| *member == principal)
+            .map(|(_, role)| *role)
+    }
+
+    // The strongest relationship `principal` has with the event, if any
+    fn access(&self, principal: Principal) -> Option<EventAccess> {
+        if self.organizer == principal {
+            Some(EventAccess::Organizer)
+        } else if let Some(role) = self.co_organizer_role(principal) {
+            Some(EventAccess::CoOrganizer(role))
+        } else if self.staff.contains(&principal) {
+            Some(EventAccess::Staff)
+        } else {
+            None
+        }
+    }
+
+    // Editing the event, issuing comps and running its lifecycle
+    fn is_event_admin(&self, principal: Principal) -> bool {
+        self.organizer == principal
+            || self.co_organizer_role(principal) == Some(OrganizerRole::FullAdmin)
+    }
+
+    fn can_manage_staff(&self, principal: Principal) -> bool {
+        self.is_event_admin(principal)
+            || self.co_organizer_role(principal) == Some(OrganizerRole::ScannerManager)
+    }
+
+    fn can_manage_finances(&self, principal: Principal) -> bool {
+        self.is_event_admin(principal)
+            || self.co_organizer_role(principal) == Some(OrganizerRole::FinanceOnly)
+    }
+
+    // Scanning tickets and seeing who holds them
+    fn can_check_in(&self, principal: Principal) -> bool {
+        self.can_manage_staff(principal) || self.staff.contains(&principal)
+    }
+
+    // Takes tickets of `tier_name` out of the inventory (negative `delta`) or puts them back.
+    // Counts never wrap or exceed what exists; on failure the event is left untouched.
+    fn adjust_inventory(&mut self, tier_name: &str, delta: i64) -> Result<(), TicketingError> {
+        let shift = |count: u32, limit: u32| {
+            u32::try_from(count as i64 + delta).ok()
+                .filter(|count| *count <= limit)
+                .ok_or(TicketingError::InventoryInconsistent)
+        };
+
+        let available_tickets = shift(self.available_tickets, self.total_tickets)?;
+        if let Some(tier) = self.tier_mut(tier_name) {
+            tier.available = shift(tier.available, tier.total)?;
+        }
+        self.available_tickets = available_tickets;
+        Ok(())
+    }
+
+    fn is_invited(&self, principal: Principal) -> bool {
+        match &self.allowlist {
+            Some(allowlist) if !allowlist.is_empty() => allowlist.contains(&principal),
+            _ => true,
+        }
+    }
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct Ticket {
     pub id: u64,
     pub event_id: u64,
+    pub purchase_id: u64,
     pub owner: Principal,
     pub seat_number: String,
+    pub tier: String,
     pub purchase_time: u64,
     pub is_used: bool,
+    pub used_at: Option<u64>,
+    pub used_by: Option<Principal>, // Organizer or staff member who scanned the ticket
     pub verification_code: String,
+    pub needs_reacknowledgement: bool, // Set when the event is rescheduled
+    pub is_comp: bool, // Issued free by the organizer; earns no revenue
+    pub price_paid: u64, // This ticket's share of its purchase total, after tier pricing and discounts
+    pub metadata: HashMap<String, String>, // Values for the event's custom fields
+}
+
+// A ticket signed with the canister's threshold ECDSA key, for gates that can't reach
+// the canister. The signature covers the SHA-256 of `ticket_signature_payload`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TicketSignature {
+    pub event_id: u64,
+    pub ticket_id: u64,
+    pub owner: Principal,
+    pub signature: Vec<u8>, // 64-byte (r, s) secp256k1 signature
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -39,9 +336,125 @@ pub struct Purchase {
     pub event_id: u64,
     pub buyer: Principal,
     pub quantity: u32,
+    pub requested_quantity: u32, // More than `quantity` when a partial purchase was capped
     pub total_amount: u64,
+    pub fee_amount: u64, // Platform's cut of total_amount
+    pub promo_code: Option<String>,
     pub purchase_time: u64,
     pub ticket_ids: Vec<u64>,
+    pub chargeback_flagged: bool,
+    pub receipt_hash: String, // See `compute_receipt_hash`
+    pub split_from: Option<u64>, // Purchase these tickets were carved out of by `split_purchase`
+    pub superseded_receipt_hashes: Vec<String>, // Receipts issued before `split_purchase` shrank this record
+    pub is_free: bool, // An RSVP through `claim_free_ticket` rather than a sale
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PromoCode {
+    pub code: String,
+    pub event_id: u64,
+    pub discount_bps: u16,
+    pub max_uses: u32,
+    pub remaining_uses: u32,
+    pub expires_at: Option<u64>,
+}
+
+// A pass to several events sold at one price, e.g. every day of a festival
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Bundle {
+    pub id: u64,
+    pub name: String,
+    pub organizer: Principal,
+    pub event_ids: Vec<u64>,
+    pub price_icp: u64, // In the smallest unit of payment_token, shared by every included event
+    pub payment_token: PaymentToken,
+    pub created_at: u64,
+}
+
+// Record of a privileged action, for dispute resolution
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub actor: Principal,
+    pub action: String,
+    pub event_id: Option<u64>, // None for platform-wide admin actions
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ResaleListing {
+    pub ticket_id: u64,
+    pub event_id: u64,
+    pub seller: Principal,
+    pub price_icp: u64,
+    pub listed_at: u64,
+}
+
+// A transfer awaiting the recipient's acceptance; the sender keeps the ticket until then
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TicketOffer {
+    pub ticket_id: u64,
+    pub event_id: u64,
+    pub from: Principal,
+    pub to: Principal,
+    pub offered_at: u64,
+    pub expires_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PlatformStats {
+    pub total_events: u64,
+    pub active_events: u64,
+    pub tickets_sold: u64, // Tickets currently issued; refunded ones drop out
+    pub tickets_used: u64,
+    pub revenue: Vec<(PaymentToken, u64)>, // Gross sales per token
+    pub registered_users: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Review {
+    pub event_id: u64,
+    pub reviewer: Principal,
+    pub rating: u8, // 1 to 5
+    pub comment: String,
+    pub submitted_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefundRequestStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RefundRequest {
+    pub id: u64,
+    pub ticket_id: u64,
+    pub event_id: u64,
+    pub requester: Principal,
+    pub reason: String,
+    pub requested_at: u64,
+    pub status: RefundRequestStatus,
+    pub resolved_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefundReason {
+    UserRequested,
+    EventCancelled,
+    Reschedule, // The holder opted out after the event moved
+    Oversold,
+}
+
+// A refund that was paid out, kept apart from the purchase it reverses
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RefundRecord {
+    pub ticket_id: u64,
+    pub event_id: u64,
+    pub recipient: Principal,
+    pub amount: u64,
+    pub reason: RefundReason,
+    pub time: u64,
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -49,6 +462,8 @@ pub struct UserProfile {
     pub user_principal: Principal,
     pub purchases: Vec<u64>,
     pub tickets: Vec<u64>,
+    pub waitlist_openings: Vec<u64>, // Events where a ticket opened up for this user
+    pub followed_events: Vec<u64>,
     pub reputation_score: u32,
     pub is_verified: bool,
 }
@@ -66,320 +481,6183 @@ pub enum TicketingError {
     TicketNotFound,
     AlreadyUsed,
     InvalidVerificationCode,
+    PaymentFailed,
+    RefundWindowClosed,
+    SaleAlreadyStarted,
+    TierNotFound,
+    InvalidTiers,
+    AmountOverflow,
+    InvalidQuantity,
+    ResalePriceTooHigh,
+    TicketListedForResale,
+    NotListedForResale,
+    EventAlreadyHappened,
+    AlreadyOnWaitlist,
+    NotOnWaitlist,
+    EventCancelled,
+    PurchaseNotFound,
+    BuyerNotVerified,
+    InvalidFee,
+    SeatUnavailable,
+    InvalidSeatMap,
+    InvalidUrl,
+    InvalidTimeWindow,
+    PromoCodeInvalid,
+    ReservationNotFound,
+    ReservationExpired,
+    RateLimited,
+    PlatformPaused,
+    EventNotFinished,
+    InvalidRating,
+    ReviewTooLong,
+    AlreadyReviewed,
+    NotAttended,
+    ReservedPoolExhausted,
+    EventNotFree,
+    AlreadyClaimed,
+    WrongEvent,
+    BelowMinPurchase,
+    AboveMaxPurchase,
+    InvalidIdempotencyKey,
+    PurchaseInProgress,
+    InsufficientBalance,
+    RefundApprovalRequired,
+    RefundRequestNotFound,
+    RefundRequestResolved,
+    RefundAlreadyRequested,
+    ReasonTooLong,
+    EventEnded,
+    SigningFailed,
+    InvalidPriceSchedule,
+    NotTransferable,
+    InvalidCurrency,
+    NotInvited,
+    InventoryInconsistent,
+    AlreadyFollowing,
+    NotFollowing,
+    OfferNotFound,
+    OfferExpired,
+    OfferAlreadyPending,
+    ReputationTooLow,
+    InvalidCustomFields,
+    InvalidTicketMetadata,
+    BundleNotFound,
+    InvalidBundle,
+    AnonymousCaller,
+    FieldTooLong { field: String },
+    VenueDoubleBooked,
+    TicketsStillAvailable,
+    RefundInProgress,
+    CompNotRefundable,
 }
 
-// Global state
-thread_local! {
-    static EVENTS: RefCell<BTreeMap<u64, Event>> = RefCell::new(BTreeMap::new());
-    static TICKETS: RefCell<BTreeMap<u64, Ticket>> = RefCell::new(BTreeMap::new());
-    static PURCHASES: RefCell<BTreeMap<u64, Purchase>> = RefCell::new(BTreeMap::new());
-    static USER_PROFILES: RefCell<BTreeMap<Principal, UserProfile>> = RefCell::new(BTreeMap::new());
-    static USER_EVENT_PURCHASES: RefCell<HashMap<(Principal, u64), u32>> = RefCell::new(HashMap::new());
-    static EVENT_COUNTER: RefCell<u64> = RefCell::new(0);
-    static TICKET_COUNTER: RefCell<u64> = RefCell::new(0);
-    static PURCHASE_COUNTER: RefCell<u64> = RefCell::new(0);
+// Limits
+const MAX_PAGE_SIZE: u64 = 100;
+const MAX_BATCH_SIZE: usize = 100;
+const MAX_URL_LENGTH: usize = 2048;
+const MAX_REVIEW_LENGTH: usize = 2000;
+const MAX_EVENT_NAME_LENGTH: usize = 200;
+const MAX_VENUE_LENGTH: usize = 200;
+const MAX_DESCRIPTION_LENGTH: usize = 4000;
+const MAX_REFUND_REASON_LENGTH: usize = 500;
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 128;
+const MAX_CUSTOM_FIELDS: usize = 10;
+const MAX_CUSTOM_FIELD_NAME_LENGTH: usize = 64;
+const MAX_METADATA_VALUE_LENGTH: usize = 256;
+
+// Name of the implicit tier for events created without explicit tiers
+const DEFAULT_TIER_NAME: &str = "General";
+
+// Share of an event's tickets, in basis points, left when it starts counting as low on stock
+const DEFAULT_LOW_STOCK_BPS: u16 = 1000;
+
+// Category for events created without one
+const DEFAULT_CATEGORY: &str = "Other";
+
+// How long `reserve_tickets` holds tickets and how often expired holds are swept
+const RESERVATION_TTL: u64 = 5 * 60 * 1_000_000_000;
+const RESERVATION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// How long the head of a waitlist has to claim a returned ticket held for them
+const WAITLIST_CLAIM_WINDOW: u64 = 30 * 60 * 1_000_000_000;
+
+// How long after a check-in the same scanner can repeat it and still get `Ok`
+const RESCAN_WINDOW: u64 = 5 * 1_000_000_000;
+
+// How long a ticket offer waits for the recipient before it lapses
+const TICKET_OFFER_TTL: u64 = 48 * 60 * 60 * 1_000_000_000;
+
+// How often events whose sale or date has passed are closed out
+const EVENT_LIFECYCLE_INTERVAL: Duration = Duration::from_secs(60);
+
+// How close together two of an organizer's events at the same venue may start
+const VENUE_BOOKING_BUFFER: u64 = 4 * 60 * 60 * 1_000_000_000;
+
+// How long after its start time an event is reported as Live
+const EVENT_LIVE_WINDOW: u64 = 12 * 60 * 60 * 1_000_000_000;
+
+// Default per-principal limit on purchases and event creation: 10 calls a minute
+const DEFAULT_RATE_LIMIT_WINDOW: u64 = 60 * 1_000_000_000;
+const DEFAULT_RATE_LIMIT_MAX_CALLS: u32 = 10;
+
+// Platform fee, in basis points of each purchase
+const MAX_PLATFORM_FEE_BPS: u16 = 1000;
+
+// Reputation scoring
+const INITIAL_REPUTATION: u32 = 100;
+const MAX_REPUTATION: u32 = 1000;
+const ATTENDANCE_REWARD: i64 = 10;
+const LATE_REFUND_PENALTY: i64 = -20;
+const CHARGEBACK_PENALTY: i64 = -100;
+const DEFAULT_NO_SHOW_PENALTY: u32 = 15;
+// How long after an event unused tickets count as no-shows
+const NO_SHOW_GRACE_PERIOD: u64 = 24 * 60 * 60 * 1_000_000_000;
+// How long holders of a rescheduled event can still get a refund
+const RESCHEDULE_GRACE_PERIOD: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+// Refunds this close to the event count as a no-show
+const LATE_REFUND_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// Threshold ECDSA key tickets are signed with; local replicas only have "dfx_test_key"
+const ECDSA_KEY_NAME: &str = "key_1";
+// Prefix of every signed ticket payload, so the signatures can't be replayed for anything else
+const TICKET_SIGNATURE_DOMAIN: &[u8] = b"ticket-signature-v1";
+
+// Method called on an event's notify_canister with (event_id, ticket_id, time)
+// whenever one of its tickets is scanned
+const TICKET_USED_CALLBACK: &str = "ticket_used";
+
+// Token ledgers (ICRC-1 / ICRC-2) interface
+const ICP_LEDGER_CANISTER_ID: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+const CKUSDC_LEDGER_CANISTER_ID: &str = "xevnm-gaaaa-aaaar-qafnq-cai";
+const ICP_LEDGER_FEE: u64 = 10_000;
+const CKUSDC_LEDGER_FEE: u64 = 10_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
 }
 
-// Utility functions
-fn generate_verification_code(ticket_id: u64, event_id: u64) -> String {
-    format!("{:08X}-{:08X}", ticket_id, event_id)
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
 }
 
-fn get_or_create_user_profile(principal: Principal) -> UserProfile {
-    USER_PROFILES.with(|profiles| {
-        profiles.borrow_mut().entry(principal).or_insert(UserProfile {
-            user_principal: principal,
-            purchases: Vec::new(),
-            tickets: Vec::new(),
-            reputation_score: 100,
-            is_verified: false,
-        }).clone()
-    })
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
 }
 
-// Canister methods
-#[init]
-fn init() {
-    ic_cdk::println!("Event Ticketing System initialized");
+#[derive(CandidType, Deserialize, Debug)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
 }
 
-#[update]
-fn create_event(
-    name: String,
-    description: String,
-    venue: String,
-    date: u64,
-    total_tickets: u32,
-    price_icp: u64,
-    max_tickets_per_user: u32,
-    sale_start_time: u64,
-    sale_end_time: u64,
-) -> Result<u64, TicketingError> {
-    let caller = ic_cdk::caller();
-    let event_id = EVENT_COUNTER.with(|counter| {
-        let mut counter = counter.borrow_mut();
-        *counter += 1;
-        *counter
+#[derive(CandidType, Deserialize, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// Global state
+thread_local! {
+    static EVENTS: RefCell<BTreeMap<u64, Event>> = const { RefCell::new(BTreeMap::new()) };
+    static TICKETS: RefCell<BTreeMap<u64, Ticket>> = const { RefCell::new(BTreeMap::new()) };
+    static PURCHASES: RefCell<BTreeMap<u64, Purchase>> = const { RefCell::new(BTreeMap::new()) };
+    // Purchase created for each (buyer, idempotency key) pair
+    static IDEMPOTENCY_KEYS: RefCell<HashMap<(Principal, String), u64>> = RefCell::new(HashMap::new());
+    // Keys whose purchase is still in flight; not persisted since upgrades wait for outstanding calls
+    static PENDING_IDEMPOTENCY_KEYS: RefCell<BTreeSet<(Principal, String)>> = const { RefCell::new(BTreeSet::new()) };
+    static USER_PROFILES: RefCell<BTreeMap<Principal, UserProfile>> = const { RefCell::new(BTreeMap::new()) };
+    // Tickets each principal has bought (or has on hold) per event; transfers don't change it
+    static USER_EVENT_PURCHASES: RefCell<HashMap<(Principal, u64), u32>> = RefCell::new(HashMap::new());
+    // Tickets each principal currently owns (or has on hold) per event, following transfers;
+    // rebuilt from TICKETS and RESERVATIONS after upgrades
+    static USER_EVENT_TICKETS: RefCell<HashMap<(Principal, u64), u32>> = RefCell::new(HashMap::new());
+    static ADMIN: RefCell<Option<Principal>> = const { RefCell::new(None) };
+    static PLATFORM_FEE_BPS: RefCell<u16> = const { RefCell::new(0) };
+    // Emergency stop for sales, transfers and event creation
+    static PLATFORM_PAUSED: RefCell<bool> = const { RefCell::new(false) };
+    // Reputation each unused ticket costs its holder once the event is over
+    static NO_SHOW_PENALTY: RefCell<u32> = const { RefCell::new(DEFAULT_NO_SHOW_PENALTY) };
+    // Fees held in escrow on behalf of the platform, withdrawable by the admin
+    static PLATFORM_FEE_BALANCE: RefCell<BTreeMap<PaymentToken, u64>> = const { RefCell::new(BTreeMap::new()) };
+    // Sales proceeds, net of the platform fee, held in escrow for each organizer
+    static ORGANIZER_BALANCES: RefCell<HashMap<(Principal, PaymentToken), u64>> = RefCell::new(HashMap::new());
+    static RATE_LIMIT_WINDOW: RefCell<u64> = const { RefCell::new(DEFAULT_RATE_LIMIT_WINDOW) };
+    static RATE_LIMIT_MAX_CALLS: RefCell<u32> = const { RefCell::new(DEFAULT_RATE_LIMIT_MAX_CALLS) };
+    // Timestamps of each principal's recent rate-limited calls; not persisted
+    static RECENT_CALLS: RefCell<HashMap<Principal, Vec<u64>>> = RefCell::new(HashMap::new());
+    static EVENT_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static SERIES_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static TICKET_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static PURCHASE_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static RESERVATION_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static REFUND_REQUEST_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    static BUNDLE_COUNTER: RefCell<u64> = const { RefCell::new(0) };
+    // Secret seed from `raw_rand` that makes verification codes unguessable
+    static VERIFICATION_SEED: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    static VERIFICATION_NONCE: RefCell<u64> = const { RefCell::new(0) };
+    // Index from verification code to ticket id; rebuilt from TICKETS after upgrades
+    static VERIFICATION_CODES: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
+    // SEC1-encoded public key for ticket signatures; not persisted since it's fetched again after upgrades
+    static VERIFICATION_PUBKEY: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    // Index from organizer to the ids of their events; rebuilt from EVENTS after upgrades
+    static ORGANIZER_EVENTS: RefCell<HashMap<Principal, Vec<u64>>> = RefCell::new(HashMap::new());
+    // Lower-cased event names and every word-started suffix of them, for autocomplete.
+    // Archived events are left out; rebuilt from EVENTS after upgrades.
+    static EVENT_NAME_INDEX: RefCell<BTreeMap<String, BTreeSet<u64>>> = const { RefCell::new(BTreeMap::new()) };
+    // Keyed by (event id, upper-cased code)
+    static PROMO_CODES: RefCell<BTreeMap<(u64, String), PromoCode>> = const { RefCell::new(BTreeMap::new()) };
+    static RESALE_LISTINGS: RefCell<BTreeMap<u64, ResaleListing>> = const { RefCell::new(BTreeMap::new()) };
+    static BUNDLES: RefCell<BTreeMap<u64, Bundle>> = const { RefCell::new(BTreeMap::new()) };
+    // Append-only, oldest first
+    static AUDIT_LOG: RefCell<Vec<AuditEntry>> = const { RefCell::new(Vec::new()) };
+    // Pending offers keyed by ticket id
+    static TICKET_OFFERS: RefCell<BTreeMap<u64, TicketOffer>> = const { RefCell::new(BTreeMap::new()) };
+    static RESERVATIONS: RefCell<BTreeMap<u64, Reservation>> = const { RefCell::new(BTreeMap::new()) };
+    // Per-event seats held or taken by tickets; rebuilt from TICKETS after upgrades
+    static TAKEN_SEATS: RefCell<BTreeMap<u64, BTreeSet<String>>> = const { RefCell::new(BTreeMap::new()) };
+    static WAITLISTS: RefCell<BTreeMap<u64, Vec<Principal>>> = const { RefCell::new(BTreeMap::new()) };
+    static REVIEWS: RefCell<BTreeMap<u64, Vec<Review>>> = const { RefCell::new(BTreeMap::new()) };
+    static REFUND_REQUESTS: RefCell<BTreeMap<u64, RefundRequest>> = const { RefCell::new(BTreeMap::new()) };
+    // Append-only, oldest first
+    static REFUNDS: RefCell<Vec<RefundRecord>> = const { RefCell::new(Vec::new()) };
+    // Tickets whose resale payment is in flight; not persisted since upgrades wait for outstanding calls
+    static RESALES_IN_PROGRESS: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+    // Tickets whose refund payout is in flight; not persisted for the same reason
+    static REFUNDS_IN_PROGRESS: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+// Snapshot of the global state written to stable memory across upgrades
+#[derive(CandidType, Deserialize)]
+struct StableState {
+    events: BTreeMap<u64, Event>,
+    tickets: BTreeMap<u64, Ticket>,
+    purchases: BTreeMap<u64, Purchase>,
+    idempotency_keys: HashMap<(Principal, String), u64>,
+    promo_codes: BTreeMap<(u64, String), PromoCode>,
+    resale_listings: BTreeMap<u64, ResaleListing>,
+    bundles: BTreeMap<u64, Bundle>,
+    audit_log: Vec<AuditEntry>,
+    ticket_offers: BTreeMap<u64, TicketOffer>,
+    reservations: BTreeMap<u64, Reservation>,
+    waitlists: BTreeMap<u64, Vec<Principal>>,
+    reviews: BTreeMap<u64, Vec<Review>>,
+    refund_requests: BTreeMap<u64, RefundRequest>,
+    refunds: Vec<RefundRecord>,
+    user_profiles: BTreeMap<Principal, UserProfile>,
+    user_event_purchases: HashMap<(Principal, u64), u32>,
+    admin: Option<Principal>,
+    platform_fee_bps: u16,
+    platform_paused: bool,
+    no_show_penalty: u32,
+    platform_fee_balance: BTreeMap<PaymentToken, u64>,
+    organizer_balances: HashMap<(Principal, PaymentToken), u64>,
+    rate_limit_window: u64,
+    rate_limit_max_calls: u32,
+    event_counter: u64,
+    series_counter: u64,
+    ticket_counter: u64,
+    purchase_counter: u64,
+    reservation_counter: u64,
+    refund_request_counter: u64,
+    bundle_counter: u64,
+    verification_seed: Vec<u8>,
+    verification_nonce: u64,
+}
+
+// Utility functions
+// Codes are a keyed hash of the ticket id, so they can't be derived from public fields.
+// The nonce makes every regenerated code for the same ticket distinct.
+fn generate_verification_code(ticket_id: u64) -> String {
+    let nonce = VERIFICATION_NONCE.with(|nonce| {
+        let mut nonce = nonce.borrow_mut();
+        *nonce += 1;
+        *nonce
     });
 
-    let event = Event {
-        id: event_id,
-        name,
-        description,
-        venue,
-        date,
-        total_tickets,
-        available_tickets: total_tickets,
-        price_icp,
-        organizer: caller,
-        max_tickets_per_user,
-        sale_start_time,
-        sale_end_time,
-        is_active: true,
-    };
+    let digest = VERIFICATION_SEED.with(|seed| {
+        let seed = seed.borrow();
+        if seed.is_empty() {
+            ic_cdk::trap("Verification seed is not initialized yet");
+        }
 
-    EVENTS.with(|events| {
-        events.borrow_mut().insert(event_id, event);
+        let mut hasher = Sha256::new();
+        hasher.update(&*seed);
+        hasher.update(ticket_id.to_be_bytes());
+        hasher.update(nonce.to_be_bytes());
+        hasher.finalize()
     });
 
-    Ok(event_id)
+    let hex: String = digest[..8].iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!("{}-{}", &hex[..8], &hex[8..])
 }
 
-#[query]
-fn get_event(event_id: u64) -> Result<Event, TicketingError> {
-    EVENTS.with(|events| {
-        events.borrow().get(&event_id)
-            .cloned()
-            .ok_or(TicketingError::EventNotFound)
-    })
-}
+// Generates a globally unique code for the ticket and records it in the code index
+fn issue_verification_code(ticket_id: u64) -> String {
+    loop {
+        let code = generate_verification_code(ticket_id);
+        let is_new = VERIFICATION_CODES.with(|codes| {
+            let mut codes = codes.borrow_mut();
+            if codes.contains_key(&code) {
+                false
+            } else {
+                codes.insert(code.clone(), ticket_id);
+                true
+            }
+        });
 
-#[query]
-fn get_all_events() -> Vec<Event> {
-    EVENTS.with(|events| {
-        events.borrow().values().cloned().collect()
-    })
+        if is_new {
+            return code;
+        }
+    }
 }
 
-#[query]
-fn get_active_events() -> Vec<Event> {
-    let current_time = time();
-    EVENTS.with(|events| {
-        events.borrow().values()
-            .filter(|event| event.is_active && event.sale_end_time > current_time)
-            .cloned()
-            .collect()
-    })
+fn revoke_verification_code(code: &str) {
+    VERIFICATION_CODES.with(|codes| {
+        codes.borrow_mut().remove(code);
+    });
 }
 
-#[update]
-fn purchase_tickets(event_id: u64, quantity: u32) -> Result<Purchase, TicketingError> {
-    let caller = ic_cdk::caller();
-    let current_time = time();
+async fn ensure_verification_seed() {
+    if VERIFICATION_SEED.with(|seed| !seed.borrow().is_empty()) {
+        return;
+    }
 
-    // Get event and validate
-    let event = EVENTS.with(|events| {
-        events.borrow().get(&event_id)
-            .cloned()
-            .ok_or(TicketingError::EventNotFound)
-    })?;
+    let (bytes,) = raw_rand().await
+        .unwrap_or_else(|(code, message)| {
+            ic_cdk::trap(&format!("Failed to fetch randomness ({:?}): {}", code, message))
+        });
 
-    if !event.is_active {
-        return Err(TicketingError::EventInactive);
-    }
+    VERIFICATION_SEED.with(|seed| {
+        let mut seed = seed.borrow_mut();
+        // Another call may have raced us to it
+        if seed.is_empty() {
+            *seed = bytes;
+        }
+    });
+}
 
-    if current_time < event.sale_start_time {
-        return Err(TicketingError::SaleNotStarted);
+// `raw_rand` can't be awaited in init/post_upgrade, so fetch the seed right afterwards
+fn schedule_verification_seed() {
+    if VERIFICATION_SEED.with(|seed| seed.borrow().is_empty()) {
+        ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(ensure_verification_seed()));
     }
+}
 
-    if current_time > event.sale_end_time {
-        return Err(TicketingError::SaleEnded);
+fn ecdsa_key_id() -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: ECDSA_KEY_NAME.to_string(),
     }
+}
 
-    if event.available_tickets < quantity {
-        return Err(TicketingError::InsufficientTickets);
+// domain || event id || ticket id || owner principal bytes, integers big-endian
+fn ticket_signature_payload(ticket: &Ticket) -> Vec<u8> {
+    let mut payload = TICKET_SIGNATURE_DOMAIN.to_vec();
+    payload.extend_from_slice(&ticket.event_id.to_be_bytes());
+    payload.extend_from_slice(&ticket.id.to_be_bytes());
+    payload.extend_from_slice(ticket.owner.as_slice());
+    payload
+}
+
+async fn ensure_verification_pubkey() -> Result<(), TicketingError> {
+    if VERIFICATION_PUBKEY.with(|key| !key.borrow().is_empty()) {
+        return Ok(());
     }
 
-    // Check user purchase limits
-    let current_user_purchases = USER_EVENT_PURCHASES.with(|purchases| {
-        purchases.borrow().get(&(caller, event_id)).copied().unwrap_or(0)
-    });
+    let argument = EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: Vec::new(),
+        key_id: ecdsa_key_id(),
+    };
 
-    if current_user_purchases + quantity > event.max_tickets_per_user {
+    let (response,) = ecdsa_public_key(argument).await
+        .map_err(|(code, message)| {
+            ic_cdk::println!("Failed to fetch the ECDSA public key ({:?}): {}", code, message);
+            TicketingError::SigningFailed
+        })?;
+
+    VERIFICATION_PUBKEY.with(|key| *key.borrow_mut() = response.public_key);
+    Ok(())
+}
+
+// Like the seed, the public key can't be fetched from init/post_upgrade directly
+fn schedule_verification_pubkey() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        ic_cdk::spawn(async {
+            let _ = ensure_verification_pubkey().await;
+        })
+    });
+}
+
+// Applies a reputation change, clamped to 0..=MAX_REPUTATION. Scoring:
+//   +10  attending an event (ticket scanned at the gate)
+//   -20  refunding within 24 hours of the event (no-show)
+//   -15  holding a ticket that was never scanned, a day after the event (admin-configurable)
+//   -100 a purchase flagged as a chargeback by the organizer
+fn adjust_reputation(user: Principal, delta: i64) {
+    let mut profile = get_or_create_user_profile(user);
+    profile.reputation_score = (profile.reputation_score as i64 + delta)
+        .clamp(0, MAX_REPUTATION as i64) as u32;
+
+    USER_PROFILES.with(|profiles| {
+        profiles.borrow_mut().insert(user, profile);
+    });
+}
+
+fn adjust_owned_tickets(owner: Principal, event_id: u64, delta: i64) {
+    USER_EVENT_TICKETS.with(|owned| {
+        let mut owned = owned.borrow_mut();
+        let count = owned.entry((owner, event_id)).or_insert(0);
+        *count = (*count as i64 + delta).max(0) as u32;
+    });
+}
+
+// Builds the tier list for an event. Without explicit tiers the event gets a single
+// default tier carrying the event-level price and capacity.
+fn build_tiers(
+    total_tickets: u32,
+    price_icp: u64,
+    tiers: Option<Vec<TicketTier>>,
+) -> Result<Vec<TicketTier>, TicketingError> {
+    let tiers = match tiers {
+        Some(tiers) if !tiers.is_empty() => tiers,
+        _ => {
+            return Ok(vec![TicketTier {
+                name: DEFAULT_TIER_NAME.to_string(),
+                price_icp,
+                total: total_tickets,
+                available: total_tickets,
+            }])
+        }
+    };
+
+    let mut names = BTreeSet::new();
+    for tier in &tiers {
+        if tier.name.is_empty() || !names.insert(tier.name.as_str()) {
+            return Err(TicketingError::InvalidTiers);
+        }
+    }
+
+    // The combined capacity must still fit in the event-level counters
+    tiers.iter()
+        .try_fold(0u32, |total, tier| total.checked_add(tier.total))
+        .ok_or(TicketingError::InvalidTiers)?;
+
+    Ok(tiers.into_iter()
+        .map(|tier| TicketTier { available: tier.total, ..tier })
+        .collect())
+}
+
+fn calculate_total_amount(price_icp: u64, quantity: u32) -> Result<u64, TicketingError> {
+    price_icp.checked_mul(quantity as u64)
+        .ok_or(TicketingError::AmountOverflow)
+}
+
+fn require_admin(principal: Principal) -> Result<(), TicketingError> {
+    if ADMIN.with(|admin| *admin.borrow()) == Some(principal) {
+        Ok(())
+    } else {
+        Err(TicketingError::Unauthorized)
+    }
+}
+
+// Sliding-window limit shared by purchases and event creation. Records the call
+// when it is allowed; a zero window or limit turns rate limiting off.
+fn check_rate_limit(caller: Principal, current_time: u64) -> Result<(), TicketingError> {
+    let window = RATE_LIMIT_WINDOW.with(|window| *window.borrow());
+    let max_calls = RATE_LIMIT_MAX_CALLS.with(|max_calls| *max_calls.borrow());
+    if window == 0 || max_calls == 0 {
+        return Ok(());
+    }
+
+    RECENT_CALLS.with(|recent_calls| {
+        let mut recent_calls = recent_calls.borrow_mut();
+        let calls = recent_calls.entry(caller).or_default();
+        calls.retain(|call_time| current_time.saturating_sub(*call_time) < window);
+
+        if calls.len() >= max_calls as usize {
+            return Err(TicketingError::RateLimited);
+        }
+
+        calls.push(current_time);
+        Ok(())
+    })
+}
+
+// Events and tickets owned by the anonymous principal could never be managed or used
+fn require_authenticated(principal: Principal) -> Result<(), TicketingError> {
+    if principal == Principal::anonymous() {
+        return Err(TicketingError::AnonymousCaller);
+    }
+    Ok(())
+}
+
+fn require_not_paused() -> Result<(), TicketingError> {
+    if PLATFORM_PAUSED.with(|paused| *paused.borrow()) {
+        Err(TicketingError::PlatformPaused)
+    } else {
+        Ok(())
+    }
+}
+
+// Checks on the buyer every purchase path (and `can_purchase`) runs before looking at the event
+fn check_purchase_caller(caller: Principal, current_time: u64) -> Result<(), TicketingError> {
+    require_authenticated(caller)?;
+    require_not_paused()?;
+    check_rate_limit(caller, current_time)
+}
+
+fn calculate_platform_fee(total_amount: u64) -> u64 {
+    let fee_bps = PLATFORM_FEE_BPS.with(|fee| *fee.borrow());
+    (total_amount as u128 * fee_bps as u128 / 10_000) as u64
+}
+
+fn apply_discount(amount: u64, discount_bps: u16) -> u64 {
+    amount - (amount as u128 * discount_bps as u128 / 10_000) as u64
+}
+
+// What a refund of `ticket` pays out: what was paid for it, minus its share of the
+// platform fee, which is non-refundable. The share rounds up so a purchase's refunds
+// never add up to more than the organizer was credited for it.
+fn refund_amount(ticket: &Ticket) -> u64 {
+    let fee_share = PURCHASES.with(|purchases| {
+        purchases.borrow().get(&ticket.purchase_id)
+            .filter(|purchase| purchase.total_amount > 0)
+            .map(|purchase| {
+                let share = (purchase.fee_amount as u128 * ticket.price_paid as u128)
+                    .div_ceil(purchase.total_amount as u128);
+                share as u64
+            })
+            .unwrap_or(0)
+    });
+    ticket.price_paid.saturating_sub(fee_share)
+}
+
+// What refunding every ticket still out for the event would take from the organizer
+fn event_refund_liability(event: &Event) -> u64 {
+    TICKETS.with(|tickets| {
+        tickets.borrow().values()
+            .filter(|ticket| ticket.event_id == event.id)
+            .map(refund_amount)
+            .sum()
+    })
+}
+
+// The sale has to open before it closes, close no later than the event and the
+// event itself has to be in the future
+fn validate_time_window(
+    date: u64,
+    sale_start_time: u64,
+    sale_end_time: u64,
+    current_time: u64,
+) -> Result<(), TicketingError> {
+    if sale_start_time >= sale_end_time || sale_end_time > date || date <= current_time {
+        return Err(TicketingError::InvalidTimeWindow);
+    }
+    Ok(())
+}
+
+// Lengths are in bytes
+fn validate_event_text(name: &str, description: &str, venue: &str) -> Result<(), TicketingError> {
+    let fields = [
+        ("name", name, MAX_EVENT_NAME_LENGTH),
+        ("description", description, MAX_DESCRIPTION_LENGTH),
+        ("venue", venue, MAX_VENUE_LENGTH),
+    ];
+    for (field, value, max_length) in fields {
+        if value.len() > max_length {
+            return Err(TicketingError::FieldTooLong { field: field.to_string() });
+        }
+    }
+    Ok(())
+}
+
+fn venue_slots_overlap(date: u64, other_date: u64) -> bool {
+    date.abs_diff(other_date) < VENUE_BOOKING_BUFFER
+}
+
+// An organizer can't have two events at the same venue (compared case-insensitively)
+// starting less than VENUE_BOOKING_BUFFER apart. Only cancelling an event frees its
+// slot; one whose sale is paused still takes place.
+fn check_venue_available(organizer: Principal, venue: &str, date: u64) -> Result<(), TicketingError> {
+    let venue = venue.trim().to_lowercase();
+    let double_booked = EVENTS.with(|events| {
+        events.borrow().values().any(|event| {
+            event.organizer == organizer
+                && !event.cancelled
+                && event.venue.trim().to_lowercase() == venue
+                && venue_slots_overlap(event.date, date)
+        })
+    });
+
+    if double_booked {
+        return Err(TicketingError::VenueDoubleBooked);
+    }
+    Ok(())
+}
+
+// Occurrences of a series share a venue, so they have to be spaced out like any other booking
+fn check_series_dates_apart(dates: &[u64]) -> Result<(), TicketingError> {
+    let mut sorted = dates.to_vec();
+    sorted.sort_unstable();
+    if sorted.windows(2).any(|pair| venue_slots_overlap(pair[0], pair[1])) {
+        return Err(TicketingError::VenueDoubleBooked);
+    }
+    Ok(())
+}
+
+fn validate_presale_start(presale_start_time: Option<u64>, sale_start_time: u64) -> Result<(), TicketingError> {
+    if presale_start_time.is_some_and(|start| start >= sale_start_time) {
+        return Err(TicketingError::InvalidTimeWindow);
+    }
+    Ok(())
+}
+
+fn validate_auto_extend(auto_extend: Option<u64>, auto_extend_max: Option<u64>) -> Result<(), TicketingError> {
+    if auto_extend == Some(0) || auto_extend_max == Some(0) {
+        return Err(TicketingError::InvalidTimeWindow);
+    }
+    Ok(())
+}
+
+fn validate_url(url: &str) -> Result<(), TicketingError> {
+    if url.trim().is_empty() || url.len() > MAX_URL_LENGTH {
+        return Err(TicketingError::InvalidUrl);
+    }
+    Ok(())
+}
+
+// Seat labels must be unique and there must be a seat for every ticket
+fn validate_seat_map(seat_map: &[String], total_tickets: u32) -> Result<(), TicketingError> {
+    if seat_map.is_empty() {
+        return Ok(());
+    }
+
+    let unique: BTreeSet<&String> = seat_map.iter().collect();
+    if unique.len() != seat_map.len() || seat_map.len() < total_tickets as usize {
+        return Err(TicketingError::InvalidSeatMap);
+    }
+
+    Ok(())
+}
+
+// Field names must be non-empty and unique
+fn validate_custom_fields(fields: &[String]) -> Result<(), TicketingError> {
+    let unique: BTreeSet<&String> = fields.iter().collect();
+    let malformed = fields.iter()
+        .any(|field| field.trim().is_empty() || field.len() > MAX_CUSTOM_FIELD_NAME_LENGTH);
+    if fields.len() > MAX_CUSTOM_FIELDS || unique.len() != fields.len() || malformed {
+        return Err(TicketingError::InvalidCustomFields);
+    }
+    Ok(())
+}
+
+// Every declared field needs a value and nothing undeclared may be passed along
+fn validate_ticket_metadata(fields: &[String], metadata: &HashMap<String, String>) -> Result<(), TicketingError> {
+    let complete = fields.iter()
+        .all(|field| metadata.get(field).is_some_and(|value| !value.trim().is_empty()));
+    let well_formed = metadata.iter()
+        .all(|(key, value)| fields.contains(key) && value.len() <= MAX_METADATA_VALUE_LENGTH);
+    if !complete || !well_formed {
+        return Err(TicketingError::InvalidTicketMetadata);
+    }
+    Ok(())
+}
+
+// Steps must be given in strictly increasing `effective_from` order
+fn validate_price_schedule(schedule: Vec<PriceStep>) -> Result<Vec<PriceStep>, TicketingError> {
+    let sorted = schedule.windows(2)
+        .all(|steps| steps[0].effective_from < steps[1].effective_from);
+    if schedule.is_empty() || !sorted {
+        return Err(TicketingError::InvalidPriceSchedule);
+    }
+    Ok(schedule)
+}
+
+// Row-and-column layouts need a seat for every ticket
+fn validate_seat_numbering(seat_numbering: SeatNumbering, total_tickets: u32) -> Result<(), TicketingError> {
+    if let SeatNumbering::RowCol { rows, cols } = seat_numbering {
+        if (rows as u64) * (cols as u64) < total_tickets as u64 {
+            return Err(TicketingError::InvalidSeatMap);
+        }
+    }
+    Ok(())
+}
+
+// Events without their own threshold are low on stock once 10% of their tickets remain
+fn inventory_status(event: &Event) -> InventoryStatus {
+    let remaining = event.available_tickets.saturating_sub(event.reserved_tickets);
+    let threshold = event.low_stock_threshold
+        .unwrap_or((event.total_tickets as u64 * DEFAULT_LOW_STOCK_BPS as u64 / 10_000) as u32);
+
+    if remaining == 0 {
+        InventoryStatus::SoldOut
+    } else if remaining <= threshold {
+        InventoryStatus::LowStock { remaining }
+    } else {
+        InventoryStatus::Available
+    }
+}
+
+// Spreadsheet-style row names: A..Z, then AA, AB, ...
+fn row_label(mut row: u32) -> String {
+    let mut label = Vec::new();
+    loop {
+        label.push(b'A' + (row % 26) as u8);
+        if row < 26 {
+            break;
+        }
+        row = row / 26 - 1;
+    }
+    label.reverse();
+    String::from_utf8(label).expect("Row labels are ASCII")
+}
+
+// The lowest free seat under the event's numbering scheme; None for general admission
+fn next_numbered_seat(event: &Event) -> Option<String> {
+    TAKEN_SEATS.with(|taken| {
+        let taken = taken.borrow();
+        let is_free = |seat: &String| !taken.get(&event.id).is_some_and(|taken| taken.contains(seat));
+
+        match event.seat_numbering {
+            SeatNumbering::Sequential => (1u64..)
+                .map(|number| number.to_string())
+                .find(is_free),
+            SeatNumbering::RowCol { rows, cols } => (0..rows)
+                .flat_map(|row| (1..=cols).map(move |col| format!("{}{}", row_label(row), col)))
+                .find(is_free),
+            SeatNumbering::None => None,
+        }
+    })
+}
+
+// Checks that every requested seat exists in the event's seat map and is free.
+// General admission events take no seat requests.
+fn validate_seat_request(event: &Event, seats: &[String], quantity: u32) -> Result<(), TicketingError> {
+    if event.seat_map.is_empty() {
+        return if seats.is_empty() {
+            Ok(())
+        } else {
+            Err(TicketingError::SeatUnavailable)
+        };
+    }
+
+    if seats.len() != quantity as usize {
+        return Err(TicketingError::InvalidQuantity);
+    }
+
+    let requested: BTreeSet<&String> = seats.iter().collect();
+    if requested.len() != seats.len() {
+        return Err(TicketingError::SeatUnavailable);
+    }
+
+    TAKEN_SEATS.with(|taken| {
+        let taken = taken.borrow();
+        let event_taken = taken.get(&event.id);
+        for seat in seats {
+            let is_taken = event_taken.is_some_and(|taken| taken.contains(seat));
+            if !event.seat_map.contains(seat) || is_taken {
+                return Err(TicketingError::SeatUnavailable);
+            }
+        }
+        Ok(())
+    })
+}
+
+// Picks the first free seat for flows where the holder doesn't choose one.
+// General admission events get no seat.
+fn assign_free_seat(event: &Event) -> Result<Vec<String>, TicketingError> {
+    if event.seat_map.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    TAKEN_SEATS.with(|taken| {
+        let taken = taken.borrow();
+        let event_taken = taken.get(&event.id);
+        event.seat_map.iter()
+            .find(|seat| !event_taken.is_some_and(|taken| taken.contains(*seat)))
+            .map(|seat| vec![seat.clone()])
+            .ok_or(TicketingError::SeatUnavailable)
+    })
+}
+
+// Takes every seat in `seats` or, if any of them is already taken, none of them
+fn claim_seats(event_id: u64, seats: &[String]) -> Result<(), TicketingError> {
+    TAKEN_SEATS.with(|taken| {
+        let mut taken = taken.borrow_mut();
+        let event_taken = taken.entry(event_id).or_default();
+
+        if seats.iter().any(|seat| event_taken.contains(seat)) {
+            return Err(TicketingError::SeatUnavailable);
+        }
+
+        event_taken.extend(seats.iter().cloned());
+        Ok(())
+    })
+}
+
+fn take_seats(event_id: u64, seats: &[String]) {
+    TAKEN_SEATS.with(|taken| {
+        taken.borrow_mut().entry(event_id).or_default().extend(seats.iter().cloned());
+    });
+}
+
+fn release_seats(event_id: u64, seats: &[String]) {
+    TAKEN_SEATS.with(|taken| {
+        if let Some(taken) = taken.borrow_mut().get_mut(&event_id) {
+            for seat in seats {
+                taken.remove(seat);
+            }
+        }
+    });
+}
+
+// What counts against `user`'s per-user limit. Both what they have bought and what they
+// hold count, so neither transferring tickets away nor collecting them gets around it.
+fn tickets_counted_against_limit(user: Principal, event_id: u64) -> u32 {
+    let purchased = USER_EVENT_PURCHASES.with(|purchases| {
+        purchases.borrow().get(&(user, event_id)).copied().unwrap_or(0)
+    });
+    let owned = USER_EVENT_TICKETS.with(|owned| {
+        owned.borrow().get(&(user, event_id)).copied().unwrap_or(0)
+    });
+    purchased.max(owned)
+}
+
+struct PurchaseRequest {
+    event_id: u64,
+    quantity: u32,
+    tier_name: Option<String>,
+    seats: Vec<String>,
+    promo_code: Option<String>,
+    allow_partial: bool, // Cap `quantity` to what the buyer may still get instead of failing
+    metadata: Option<HashMap<String, String>>, // Custom field values; None where the buyer isn't asked
+}
+
+// Outcome of a successful purchase validation
+struct PurchaseQuote {
+    tier_name: String,
+    total_amount: u64,
+    payment_token: PaymentToken,
+    promo_code: Option<String>, // Normalized code to redeem, if any
+}
+
+// Every check a purchase has to pass. Shared by `purchase_tickets` and the
+// `can_purchase` dry run so the two never diverge.
+fn validate_purchase(
+    caller: Principal,
+    request: &PurchaseRequest,
+    current_time: u64,
+) -> Result<PurchaseQuote, TicketingError> {
+    let PurchaseRequest { event_id, quantity, .. } = *request;
+
+    if quantity == 0 {
+        return Err(TicketingError::InvalidQuantity);
+    }
+
+    // Get event and validate
+    let event = EVENTS.with(|events| {
+        events.borrow().get(&event_id)
+            .cloned()
+            .ok_or(TicketingError::EventNotFound)
+    })?;
+
+    if event.cancelled {
+        return Err(TicketingError::EventCancelled);
+    }
+
+    if !event.is_active {
+        return Err(TicketingError::EventInactive);
+    }
+
+    if current_time < event.sale_start_time {
+        let presale_open = event.presale_start_time.is_some_and(|start| current_time >= start);
+        let allowlisted = event.allowlist.as_ref().is_some_and(|allowlist| allowlist.contains(&caller));
+        if !(presale_open && allowlisted) {
+            return Err(TicketingError::SaleNotStarted);
+        }
+    }
+
+    if current_time > event.sale_end_time {
+        return Err(TicketingError::SaleEnded);
+    }
+
+    if quantity < event.min_tickets_per_purchase {
+        return Err(TicketingError::BelowMinPurchase);
+    }
+
+    if quantity > event.max_tickets_per_purchase {
+        return Err(TicketingError::AboveMaxPurchase);
+    }
+
+    if event.requires_verified_buyers || event.min_reputation.is_some() {
+        let profile = get_or_create_user_profile(caller);
+
+        if event.requires_verified_buyers && !profile.is_verified {
+            return Err(TicketingError::BuyerNotVerified);
+        }
+
+        if event.min_reputation.is_some_and(|min| profile.reputation_score < min) {
+            return Err(TicketingError::ReputationTooLow);
+        }
+    }
+
+    // With a presale the allowlist only gates the early window; the public sale is open to all
+    if event.presale_start_time.is_none() && !event.is_invited(caller) {
+        return Err(TicketingError::NotInvited);
+    }
+
+    if let Some(metadata) = &request.metadata {
+        validate_ticket_metadata(&event.custom_fields, metadata)?;
+    }
+
+    // Purchases without a tier go to the event's first (default) tier
+    let tier = match &request.tier_name {
+        Some(name) => event.tier(name),
+        None => event.tiers.first(),
+    }.ok_or(TicketingError::TierNotFound)?;
+
+    // Reserved tickets are only handed out as comps
+    let publicly_available = event.available_tickets.saturating_sub(event.reserved_tickets);
+    if tier.available < quantity || publicly_available < quantity {
+        return Err(TicketingError::InsufficientTickets);
+    }
+
+    validate_seat_request(&event, &request.seats, quantity)?;
+
+    let exceeds_limit = tickets_counted_against_limit(caller, event_id).checked_add(quantity)
+        .is_none_or(|total| total > event.max_tickets_per_user);
+    if exceeds_limit {
         return Err(TicketingError::ExceedsMaxTicketsPerUser);
     }
 
-    // Create purchase
-    let purchase_id = PURCHASE_COUNTER.with(|counter| {
-        let mut counter = counter.borrow_mut();
-        *counter += 1;
-        *counter
+    let mut total_amount = calculate_total_amount(event.tier_price(tier, current_time), quantity)?;
+
+    let promo_code = match &request.promo_code {
+        Some(code) => {
+            let code = code.to_uppercase();
+            let promo = PROMO_CODES.with(|codes| codes.borrow().get(&(event_id, code.clone())).cloned())
+                .ok_or(TicketingError::PromoCodeInvalid)?;
+
+            let expired = promo.expires_at.is_some_and(|expires_at| current_time > expires_at);
+            if expired || promo.remaining_uses == 0 {
+                return Err(TicketingError::PromoCodeInvalid);
+            }
+
+            total_amount = apply_discount(total_amount, promo.discount_bps);
+            Some(code)
+        }
+        None => None,
+    };
+
+    Ok(PurchaseQuote {
+        tier_name: tier.name.clone(),
+        total_amount,
+        payment_token: event.payment_token,
+        promo_code,
+    })
+}
+
+fn adjust_promo_uses(event_id: u64, code: &str, consume: bool) {
+    PROMO_CODES.with(|codes| {
+        if let Some(promo) = codes.borrow_mut().get_mut(&(event_id, code.to_string())) {
+            if consume {
+                promo.remaining_uses -= 1;
+            } else {
+                promo.remaining_uses += 1;
+            }
+        }
+    });
+}
+
+// Shrinks a partial purchase to what the buyer can still get: their remaining per-user
+// allowance, the per-order maximum and the tickets left in the tier. Requests that can't
+// be filled at all are left alone so validation reports why.
+fn cap_to_allowance(buyer: Principal, request: &mut PurchaseRequest) {
+    let Some(event) = EVENTS.with(|events| events.borrow().get(&request.event_id).cloned()) else {
+        return;
+    };
+
+    let tier = match &request.tier_name {
+        Some(name) => event.tier(name),
+        None => event.tiers.first(),
+    };
+    let Some(tier) = tier else {
+        return;
+    };
+
+    let held = tickets_counted_against_limit(buyer, request.event_id);
+
+    let capped = request.quantity
+        .min(event.max_tickets_per_user.saturating_sub(held))
+        .min(event.max_tickets_per_purchase)
+        .min(tier.available)
+        .min(event.available_tickets.saturating_sub(event.reserved_tickets));
+
+    if capped > 0 {
+        request.quantity = capped;
+        request.seats.truncate(capped as usize);
+    }
+}
+
+// Everything a validated purchase sets aside until it is paid for or abandoned
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct PurchaseHold {
+    buyer: Principal,
+    event_id: u64,
+    quantity: u32,
+    requested_quantity: u32,
+    tier_name: String,
+    seats: Vec<String>,
+    total_amount: u64,
+    payment_token: PaymentToken,
+    promo_code: Option<String>,
+    is_comp: bool,
+    metadata: HashMap<String, String>,
+}
+
+// Validates the request and takes the inventory, the buyer's per-user allowance,
+// the requested seats and a promo code use out of circulation in one step
+fn place_hold(
+    buyer: Principal,
+    mut request: PurchaseRequest,
+    current_time: u64,
+) -> Result<PurchaseHold, TicketingError> {
+    let requested_quantity = request.quantity;
+    if request.allow_partial {
+        cap_to_allowance(buyer, &mut request);
+    }
+
+    let PurchaseQuote { tier_name, total_amount, payment_token, promo_code } =
+        validate_purchase(buyer, &request, current_time)?;
+
+    let hold = PurchaseHold {
+        buyer,
+        event_id: request.event_id,
+        quantity: request.quantity,
+        requested_quantity,
+        tier_name,
+        seats: request.seats,
+        total_amount,
+        payment_token,
+        promo_code,
+        is_comp: false,
+        metadata: request.metadata.unwrap_or_default(),
+    };
+
+    // Seats go first: if any of them is gone the whole hold fails with nothing changed
+    claim_seats(hold.event_id, &hold.seats)?;
+
+    let taken = EVENTS.with(|events| {
+        events.borrow_mut().get_mut(&hold.event_id)
+            .ok_or(TicketingError::EventNotFound)?
+            .adjust_inventory(&hold.tier_name, -(hold.quantity as i64))
+    });
+    if let Err(err) = taken {
+        release_seats(hold.event_id, &hold.seats);
+        return Err(err);
+    }
+
+    USER_EVENT_PURCHASES.with(|purchases| {
+        *purchases.borrow_mut().entry((buyer, hold.event_id)).or_insert(0) += hold.quantity;
+    });
+    adjust_owned_tickets(buyer, hold.event_id, hold.quantity as i64);
+
+    if let Some(code) = &hold.promo_code {
+        adjust_promo_uses(hold.event_id, code, true);
+    }
+
+    Ok(hold)
+}
+
+// Inventory moves for undo paths that have nothing to fail back to. An inconsistency
+// is logged and the counts are left alone rather than wrapped.
+fn adjust_event_inventory(event_id: u64, tier_name: &str, delta: i64) {
+    EVENTS.with(|events| {
+        if let Some(event) = events.borrow_mut().get_mut(&event_id) {
+            if let Err(err) = event.adjust_inventory(tier_name, delta) {
+                ic_cdk::println!("Inventory of event {} not adjusted by {}: {:?}", event_id, delta, err);
+            }
+        }
+    });
+}
+
+// Hex SHA-256 over the purchase terms: buyer (length-prefixed principal bytes), event id,
+// quantity, total amount, purchase time and each ticket id, integers big-endian
+fn compute_receipt_hash(purchase: &Purchase) -> String {
+    let buyer = purchase.buyer.as_slice();
+
+    let mut hasher = Sha256::new();
+    hasher.update([buyer.len() as u8]);
+    hasher.update(buyer);
+    hasher.update(purchase.event_id.to_be_bytes());
+    hasher.update(purchase.quantity.to_be_bytes());
+    hasher.update(purchase.total_amount.to_be_bytes());
+    hasher.update(purchase.purchase_time.to_be_bytes());
+    for ticket_id in &purchase.ticket_ids {
+        hasher.update(ticket_id.to_be_bytes());
+    }
+
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Reverses `place_hold`
+fn release_hold(hold: &PurchaseHold) {
+    adjust_event_inventory(hold.event_id, &hold.tier_name, hold.quantity as i64);
+
+    USER_EVENT_PURCHASES.with(|purchases| {
+        if let Some(count) = purchases.borrow_mut().get_mut(&(hold.buyer, hold.event_id)) {
+            *count = count.saturating_sub(hold.quantity);
+        }
+    });
+    adjust_owned_tickets(hold.buyer, hold.event_id, -(hold.quantity as i64));
+
+    release_seats(hold.event_id, &hold.seats);
+
+    if let Some(code) = &hold.promo_code {
+        adjust_promo_uses(hold.event_id, code, false);
+    }
+}
+
+// Mints the tickets for a paid-for hold and records the purchase
+fn complete_purchase(hold: PurchaseHold, current_time: u64) -> Purchase {
+    let PurchaseHold {
+        buyer, event_id, quantity, requested_quantity, tier_name, seats, total_amount,
+        payment_token, promo_code, is_comp, metadata,
+    } = hold;
+
+    let fee_amount = calculate_platform_fee(total_amount);
+    PLATFORM_FEE_BALANCE.with(|balance| {
+        *balance.borrow_mut().entry(payment_token).or_insert(0) += fee_amount;
+    });
+
+    let event = get_event(event_id).ok();
+    if let Some(event) = &event {
+        credit_organizer(event.organizer, payment_token, total_amount - fee_amount);
+    }
+
+    // Create purchase
+    let purchase_id = PURCHASE_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        *counter += 1;
+        *counter
+    });
+
+    let mut ticket_ids = Vec::new();
+
+    // Any remainder of the split goes to the first ticket so the shares add up to the total
+    let share = total_amount / quantity.max(1) as u64;
+    let remainder = total_amount % quantity.max(1) as u64;
+
+    // Create tickets
+    for index in 0..quantity as usize {
+        let ticket_id = TICKET_COUNTER.with(|counter| {
+            let mut counter = counter.borrow_mut();
+            *counter += 1;
+            *counter
+        });
+
+        let seat_number = match seats.get(index) {
+            Some(seat) => seat.clone(),
+            None => match event.as_ref().and_then(next_numbered_seat) {
+                Some(seat) => {
+                    take_seats(event_id, std::slice::from_ref(&seat));
+                    seat
+                }
+                None => String::new(),
+            },
+        };
+        let verification_code = issue_verification_code(ticket_id);
+
+        let ticket = Ticket {
+            id: ticket_id,
+            event_id,
+            purchase_id,
+            owner: buyer,
+            seat_number,
+            tier: tier_name.clone(),
+            purchase_time: current_time,
+            is_used: false,
+            used_at: None,
+            used_by: None,
+            verification_code,
+            needs_reacknowledgement: false,
+            is_comp,
+            price_paid: if index == 0 { share + remainder } else { share },
+            metadata: metadata.clone(),
+        };
+
+        TICKETS.with(|tickets| {
+            tickets.borrow_mut().insert(ticket_id, ticket);
+        });
+
+        ticket_ids.push(ticket_id);
+    }
+
+    let mut purchase = Purchase {
+        id: purchase_id,
+        event_id,
+        buyer,
+        quantity,
+        requested_quantity,
+        total_amount,
+        fee_amount,
+        promo_code,
+        purchase_time: current_time,
+        ticket_ids: ticket_ids.clone(),
+        chargeback_flagged: false,
+        receipt_hash: String::new(),
+        split_from: None,
+        superseded_receipt_hashes: Vec::new(),
+        is_free: false,
+    };
+    purchase.receipt_hash = compute_receipt_hash(&purchase);
+
+    // Update state
+    PURCHASES.with(|purchases| {
+        purchases.borrow_mut().insert(purchase_id, purchase.clone());
+    });
+
+    // Update user profile
+    let mut profile = get_or_create_user_profile(buyer);
+    profile.purchases.push(purchase_id);
+    profile.tickets.extend(ticket_ids);
+    profile.waitlist_openings.retain(|id| *id != event_id);
+
+    USER_PROFILES.with(|profiles| {
+        profiles.borrow_mut().insert(buyer, profile);
+    });
+
+    // A buyer no longer needs their spot on the waitlist
+    WAITLISTS.with(|waitlists| {
+        if let Some(waitlist) = waitlists.borrow_mut().get_mut(&event_id) {
+            waitlist.retain(|principal| *principal != buyer);
+        }
+    });
+
+    purchase
+}
+
+// A hold awaiting `confirm_reservation`; released automatically once it expires
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Reservation {
+    id: u64,
+    hold: PurchaseHold,
+    expires_at: u64,
+    from_waitlist: bool, // Offered to the head of the waitlist; goes back on open sale if unclaimed
+}
+
+fn release_expired_reservations() {
+    let current_time = time();
+
+    let expired: Vec<Reservation> = RESERVATIONS.with(|reservations| {
+        let mut reservations = reservations.borrow_mut();
+        let expired_ids: Vec<u64> = reservations.values()
+            .filter(|reservation| current_time > reservation.expires_at)
+            .map(|reservation| reservation.id)
+            .collect();
+
+        expired_ids.iter()
+            .filter_map(|id| reservations.remove(id))
+            .collect()
+    });
+
+    for reservation in expired {
+        release_hold(&reservation.hold);
+        if !reservation.from_waitlist {
+            notify_waitlist(reservation.hold.event_id, &reservation.hold.tier_name);
+        }
+    }
+}
+
+// Lapsed offers leave the ticket with its sender, so expiring one only drops the record
+fn expire_ticket_offers() {
+    let current_time = time();
+
+    TICKET_OFFERS.with(|offers| {
+        offers.borrow_mut().retain(|_, offer| current_time <= offer.expires_at);
+    });
+}
+
+fn schedule_reservation_sweep() {
+    ic_cdk_timers::set_timer_interval(RESERVATION_SWEEP_INTERVAL, || {
+        release_expired_reservations();
+        expire_ticket_offers();
+    });
+}
+
+// Where the sale of an event that closed with tickets left should now end, if it's set
+// to auto-extend: whole steps until the sale is open again, but never past the event or
+// more than `auto_extend_max` beyond the scheduled end
+fn auto_extended_sale_end(event: &Event, current_time: u64) -> Option<u64> {
+    let step = event.auto_extend.filter(|step| *step > 0)?;
+    let unsold = event.available_tickets > event.reserved_tickets;
+    let overdue = current_time.saturating_sub(event.sale_end_time);
+    if !event.is_active || event.cancelled || !unsold || overdue == 0 {
+        return None;
+    }
+
+    let scheduled_end = event.sale_end_time - event.auto_extended;
+    let limit = match event.auto_extend_max {
+        Some(max) => scheduled_end.saturating_add(max).min(event.date),
+        None => event.date,
+    };
+    if event.sale_end_time >= limit {
+        return None;
+    }
+
+    let steps = overdue / step + 1;
+    Some(event.sale_end_time.saturating_add(steps.saturating_mul(step)).min(limit))
+}
+
+// Deactivates events once their sale ends and marks them completed once they've
+// taken place, so `is_active` never lingers on a finished event. A grace period
+// after that, holders of tickets that were never scanned lose reputation; refunded
+// tickets are gone by then and don't count.
+fn update_event_lifecycles() {
+    let current_time = time();
+    let mut no_show_events = BTreeSet::new();
+
+    EVENTS.with(|events| {
+        for event in events.borrow_mut().values_mut() {
+            if let Some(sale_end_time) = auto_extended_sale_end(event, current_time) {
+                event.auto_extended += sale_end_time - event.sale_end_time;
+                event.sale_end_time = sale_end_time;
+            }
+
+            if event.is_active && current_time > event.sale_end_time {
+                event.is_active = false;
+            }
+
+            if !event.completed && current_time >= event.date {
+                event.completed = true;
+            }
+
+            let grace_over = current_time >= event.date.saturating_add(NO_SHOW_GRACE_PERIOD);
+            if !event.no_shows_penalized && !event.cancelled && grace_over {
+                event.no_shows_penalized = true;
+                no_show_events.insert(event.id);
+            }
+        }
+    });
+
+    let penalty = NO_SHOW_PENALTY.with(|penalty| *penalty.borrow());
+    if no_show_events.is_empty() || penalty == 0 {
+        return;
+    }
+
+    let no_shows: Vec<Principal> = TICKETS.with(|tickets| {
+        tickets.borrow().values()
+            .filter(|ticket| !ticket.is_used && no_show_events.contains(&ticket.event_id))
+            .map(|ticket| ticket.owner)
+            .collect()
+    });
+
+    for owner in no_shows {
+        adjust_reputation(owner, -(penalty as i64));
+    }
+}
+
+fn schedule_event_lifecycle_sweep() {
+    ic_cdk_timers::set_timer_interval(EVENT_LIFECYCLE_INTERVAL, update_event_lifecycles);
+}
+
+// Validates `input` and builds the event it describes; `insert_event` assigns the id
+fn build_event(organizer: Principal, input: EventInput, current_time: u64) -> Result<Event, TicketingError> {
+    let EventInput {
+        name,
+        description,
+        venue,
+        date,
+        total_tickets,
+        price_icp,
+        max_tickets_per_user,
+        presale_start_time,
+        sale_start_time,
+        sale_end_time,
+        auto_extend,
+        auto_extend_max,
+        refund_deadline,
+        tiers,
+        category,
+        requires_verified_buyers,
+        min_reputation,
+        seat_map,
+        seat_numbering,
+        custom_fields,
+        allowlist,
+        price_schedule,
+        transferable,
+        image_url,
+        metadata_url,
+        allow_venue_overlap,
+        notify_canister,
+    } = input;
+
+    validate_event_text(&name, &description, &venue)?;
+    validate_time_window(date, sale_start_time, sale_end_time, current_time)?;
+    validate_presale_start(presale_start_time, sale_start_time)?;
+    validate_auto_extend(auto_extend, auto_extend_max)?;
+
+    if !allow_venue_overlap.unwrap_or(false) {
+        check_venue_available(organizer, &venue, date)?;
+    }
+
+    if let Some(url) = &image_url {
+        validate_url(url)?;
+    }
+    if let Some(url) = &metadata_url {
+        validate_url(url)?;
+    }
+
+    let tiers = build_tiers(total_tickets, price_icp, tiers)?;
+    let total_tickets = tiers.iter().map(|tier| tier.total).sum();
+    let price_icp = tiers[0].price_icp;
+
+    let seat_map = seat_map.unwrap_or_default();
+    validate_seat_map(&seat_map, total_tickets)?;
+
+    let seat_numbering = seat_numbering.unwrap_or_default();
+    validate_seat_numbering(seat_numbering, total_tickets)?;
+
+    let price_schedule = price_schedule.map(validate_price_schedule).transpose()?.unwrap_or_default();
+
+    let custom_fields = custom_fields.unwrap_or_default();
+    validate_custom_fields(&custom_fields)?;
+
+    Ok(Event {
+        id: 0,
+        name,
+        description,
+        venue,
+        category: category.unwrap_or_else(|| DEFAULT_CATEGORY.to_string()),
+        image_url: image_url.unwrap_or_default(),
+        metadata_url,
+        notify_canister,
+        series_id: None,
+        date,
+        previous_date: None,
+        rescheduled_at: None,
+        total_tickets,
+        available_tickets: total_tickets,
+        reserved_tickets: 0,
+        low_stock_threshold: None,
+        price_icp,
+        price_schedule,
+        display_price: None,
+        payment_token: PaymentToken::ICP,
+        tiers,
+        seat_map,
+        seat_numbering,
+        custom_fields,
+        organizer,
+        staff: Vec::new(),
+        co_organizers: Vec::new(),
+        max_tickets_per_user,
+        // Per-order bounds start out as loose as the per-user limit allows
+        min_tickets_per_purchase: 1,
+        max_tickets_per_purchase: max_tickets_per_user,
+        presale_start_time,
+        sale_start_time,
+        sale_end_time,
+        auto_extend,
+        auto_extend_max,
+        auto_extended: 0,
+        // Refunds stay open until the event starts unless the organizer sets an earlier cutoff
+        refund_deadline: refund_deadline.unwrap_or(date),
+        check_in_window: None,
+        requires_verified_buyers: requires_verified_buyers.unwrap_or(false),
+        min_reputation,
+        allowlist,
+        transferable: transferable.unwrap_or(true),
+        resale_cap_bps: None,
+        refunds_require_approval: false,
+        is_active: true,
+        cancelled: false,
+        completed: false,
+        no_shows_penalized: false,
+        archived: false,
+        review_count: 0,
+        average_rating: None,
+        status: EventStatus::Upcoming,
+    })
+}
+
+fn event_status(event: &Event, current_time: u64) -> EventStatus {
+    if event.cancelled {
+        EventStatus::Cancelled
+    } else if current_time >= event.date.saturating_add(EVENT_LIVE_WINDOW) {
+        EventStatus::Completed
+    } else if current_time >= event.date {
+        EventStatus::Live
+    } else if current_time < event.sale_start_time {
+        EventStatus::Upcoming
+    } else if event.is_active && current_time <= event.sale_end_time {
+        EventStatus::OnSale
+    } else {
+        EventStatus::SaleEnded
+    }
+}
+
+// Copy of the event with its status and current prices brought up to date, for
+// returning to callers
+fn refreshed(event: &Event, current_time: u64) -> Event {
+    let mut refreshed = Event {
+        status: event_status(event, current_time),
+        ..event.clone()
+    };
+
+    if let Some(price_icp) = event.scheduled_price(current_time) {
+        refreshed.price_icp = price_icp;
+        if let Some(tier) = refreshed.tiers.first_mut() {
+            tier.price_icp = price_icp;
+        }
+    }
+
+    refreshed
+}
+
+fn insert_event(event: Event) -> u64 {
+    let event_id = EVENT_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        *counter += 1;
+        *counter
+    });
+
+    ORGANIZER_EVENTS.with(|index| {
+        index.borrow_mut().entry(event.organizer).or_default().push(event_id);
+    });
+    index_event_name(event_id, &event.name);
+
+    EVENTS.with(|events| {
+        events.borrow_mut().insert(event_id, Event { id: event_id, ..event });
+    });
+
+    event_id
+}
+
+// "Summer Jazz Night" is found by "summer jazz night", "jazz night" and "night"
+fn name_index_keys(name: &str) -> Vec<String> {
+    let name = name.to_lowercase();
+    name.char_indices()
+        .filter(|(index, c)| {
+            !c.is_whitespace() && (*index == 0 || name[..*index].ends_with(char::is_whitespace))
+        })
+        .map(|(index, _)| name[index..].to_string())
+        .collect()
+}
+
+fn index_event_name(event_id: u64, name: &str) {
+    EVENT_NAME_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in name_index_keys(name) {
+            index.entry(key).or_default().insert(event_id);
+        }
+    });
+}
+
+fn unindex_event_name(event_id: u64, name: &str) {
+    EVENT_NAME_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in name_index_keys(name) {
+            if let Some(event_ids) = index.get_mut(&key) {
+                event_ids.remove(&event_id);
+                if event_ids.is_empty() {
+                    index.remove(&key);
+                }
+            }
+        }
+    });
+}
+
+fn record_audit(actor: Principal, action: impl Into<String>, event_id: Option<u64>) {
+    AUDIT_LOG.with(|log| {
+        log.borrow_mut().push(AuditEntry {
+            timestamp: time(),
+            actor,
+            action: action.into(),
+            event_id,
+        });
+    });
+}
+
+fn get_or_create_user_profile(principal: Principal) -> UserProfile {
+    USER_PROFILES.with(|profiles| {
+        profiles.borrow_mut().entry(principal).or_insert(UserProfile {
+            user_principal: principal,
+            purchases: Vec::new(),
+            tickets: Vec::new(),
+            waitlist_openings: Vec::new(),
+            followed_events: Vec::new(),
+            reputation_score: INITIAL_REPUTATION,
+            is_verified: false,
+        }).clone()
+    })
+}
+
+// Quotes a CSV field when it contains a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Pulls `amount` of `token` from the buyer into `to` (the canister's escrow account for
+// primary sales). The buyer must have approved the canister via `icrc2_approve` beforehand.
+async fn collect_payment(
+    token: PaymentToken,
+    from: Principal,
+    to: Principal,
+    amount: u64,
+) -> Result<(), TicketingError> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account { owner: from, subaccount: None },
+        to: Account { owner: to, subaccount: None },
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+
+    let result: Result<(Result<Nat, TransferFromError>,), _> =
+        ic_cdk::call(token.ledger_canister(), "icrc2_transfer_from", (args,)).await;
+
+    match result {
+        Ok((Ok(_block_index),)) => Ok(()),
+        Ok((Err(err),)) => {
+            ic_cdk::println!("Ledger rejected payment from {}: {:?}", from, err);
+            Err(TicketingError::PaymentFailed)
+        }
+        Err((code, message)) => {
+            ic_cdk::println!("Ledger call failed ({:?}): {}", code, message);
+            Err(TicketingError::PaymentFailed)
+        }
+    }
+}
+
+// Pays `amount` of `token` out of the canister's escrow account to `to`. The ledger
+// fee comes out of `amount`, so escrow never pays out more than was set aside.
+async fn send_payment(token: PaymentToken, to: Principal, amount: u64) -> Result<(), TicketingError> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let fee = token.ledger_fee();
+    if amount <= fee {
+        return Err(TicketingError::InsufficientBalance);
+    }
+
+    let args = TransferArg {
+        from_subaccount: None,
+        to: Account { owner: to, subaccount: None },
+        amount: Nat::from(amount - fee),
+        fee: Some(Nat::from(fee)),
+        memo: None,
+        created_at_time: None,
+    };
+
+    let result: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(token.ledger_canister(), "icrc1_transfer", (args,)).await;
+
+    match result {
+        Ok((Ok(_block_index),)) => Ok(()),
+        Ok((Err(err),)) => {
+            ic_cdk::println!("Ledger rejected payout to {}: {:?}", to, err);
+            Err(TicketingError::PaymentFailed)
+        }
+        Err((code, message)) => {
+            ic_cdk::println!("Ledger call failed ({:?}): {}", code, message);
+            Err(TicketingError::PaymentFailed)
+        }
+    }
+}
+
+// Moves a ticket to a new owner, keeping both profiles and owned counts in sync
+fn reassign_ticket(ticket_id: u64, to: Principal) -> Result<(), TicketingError> {
+    let (from, event_id) = TICKETS.with(|tickets| {
+        let mut tickets = tickets.borrow_mut();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or(TicketingError::TicketNotFound)?;
+
+        let from = ticket.owner;
+        ticket.owner = to;
+        // Issue a fresh code so the previous owner can't replay the old one
+        revoke_verification_code(&ticket.verification_code);
+        ticket.verification_code = issue_verification_code(ticket_id);
+        Ok((from, ticket.event_id))
+    })?;
+
+    adjust_owned_tickets(from, event_id, -1);
+    adjust_owned_tickets(to, event_id, 1);
+
+    // An offer made by the previous owner no longer stands
+    TICKET_OFFERS.with(|offers| offers.borrow_mut().remove(&ticket_id));
+
+    USER_PROFILES.with(|profiles| {
+        if let Some(profile) = profiles.borrow_mut().get_mut(&from) {
+            profile.tickets.retain(|id| *id != ticket_id);
+        }
+    });
+
+    let mut recipient = get_or_create_user_profile(to);
+    recipient.tickets.push(ticket_id);
+
+    USER_PROFILES.with(|profiles| {
+        profiles.borrow_mut().insert(to, recipient);
+    });
+
+    Ok(())
+}
+
+// Holds a returned ticket of `tier_name` for WAITLIST_CLAIM_WINDOW for the first person on
+// the event's waitlist it can be held for. They only leave the waitlist once the hold is
+// placed; anyone it couldn't be held for keeps their spot. If nobody gets it the ticket
+// stays on open sale.
+fn notify_waitlist(event_id: u64, tier_name: &str) {
+    let current_time = time();
+
+    let waitlist = WAITLISTS.with(|waitlists| {
+        waitlists.borrow().get(&event_id).cloned().unwrap_or_default()
+    });
+
+    let next = waitlist.into_iter().find_map(|principal| {
+        let hold = get_event(event_id).and_then(|event| {
+            let request = PurchaseRequest {
+                event_id,
+                quantity: 1,
+                tier_name: Some(tier_name.to_string()),
+                seats: assign_free_seat(&event)?,
+                promo_code: None,
+                allow_partial: false,
+                metadata: None,
+            };
+            place_hold(principal, request, current_time)
+        });
+
+        match hold {
+            Ok(hold) => Some((principal, hold)),
+            Err(err) => {
+                ic_cdk::println!("Could not hold a ticket of event {} for {}: {:?}", event_id, principal, err);
+                None
+            }
+        }
+    });
+
+    if let Some((principal, hold)) = next {
+        WAITLISTS.with(|waitlists| {
+            if let Some(waitlist) = waitlists.borrow_mut().get_mut(&event_id) {
+                waitlist.retain(|waiting| *waiting != principal);
+            }
+        });
+
+        let reservation_id = RESERVATION_COUNTER.with(|counter| {
+            let mut counter = counter.borrow_mut();
+            *counter += 1;
+            *counter
+        });
+
+        RESERVATIONS.with(|reservations| {
+            reservations.borrow_mut().insert(reservation_id, Reservation {
+                id: reservation_id,
+                hold,
+                expires_at: current_time + WAITLIST_CLAIM_WINDOW,
+                from_waitlist: true,
+            });
+        });
+
+        let mut profile = get_or_create_user_profile(principal);
+        if !profile.waitlist_openings.contains(&event_id) {
+            profile.waitlist_openings.push(event_id);
+        }
+
+        USER_PROFILES.with(|profiles| {
+            profiles.borrow_mut().insert(principal, profile);
+        });
+
+        ic_cdk::println!("Notified {} of an opening for event {}", principal, event_id);
+    }
+}
+
+// Tickets of will-call events can't be transferred, offered or resold
+fn require_transferable(event_id: u64) -> Result<(), TicketingError> {
+    let transferable = EVENTS.with(|events| {
+        events.borrow().get(&event_id).map(|event| event.transferable)
+    }).ok_or(TicketingError::EventNotFound)?;
+
+    if !transferable {
+        return Err(TicketingError::NotTransferable);
+    }
+    Ok(())
+}
+
+// Listed tickets (and ones mid-resale) can't change hands or be used until delisted
+fn is_listed_for_resale(ticket_id: u64) -> bool {
+    RESALE_LISTINGS.with(|listings| listings.borrow().contains_key(&ticket_id))
+        || RESALES_IN_PROGRESS.with(|in_progress| in_progress.borrow().contains(&ticket_id))
+}
+
+fn is_refund_in_progress(ticket_id: u64) -> bool {
+    REFUNDS_IN_PROGRESS.with(|in_progress| in_progress.borrow().contains(&ticket_id))
+}
+
+// Takes a ticket out of circulation: returns its seat to the event's inventory
+// and frees up the owner's per-user allowance
+fn remove_ticket(ticket_id: u64) -> Option<Ticket> {
+    let ticket = TICKETS.with(|tickets| tickets.borrow_mut().remove(&ticket_id))?;
+    revoke_verification_code(&ticket.verification_code);
+    TICKET_OFFERS.with(|offers| offers.borrow_mut().remove(&ticket_id));
+    release_seats(ticket.event_id, std::slice::from_ref(&ticket.seat_number));
+
+    adjust_event_inventory(ticket.event_id, &ticket.tier, 1);
+
+    USER_EVENT_PURCHASES.with(|purchases| {
+        if let Some(count) = purchases.borrow_mut().get_mut(&(ticket.owner, ticket.event_id)) {
+            *count = count.saturating_sub(1);
+        }
+    });
+    adjust_owned_tickets(ticket.owner, ticket.event_id, -1);
+
+    USER_PROFILES.with(|profiles| {
+        if let Some(profile) = profiles.borrow_mut().get_mut(&ticket.owner) {
+            profile.tickets.retain(|id| *id != ticket_id);
+        }
+    });
+
+    Some(ticket)
+}
+
+fn credit_organizer(organizer: Principal, token: PaymentToken, amount: u64) {
+    ORGANIZER_BALANCES.with(|balances| {
+        *balances.borrow_mut().entry((organizer, token)).or_insert(0) += amount;
+    });
+}
+
+// Takes `amount` out of the organizer's balance, failing if they've already withdrawn it
+fn debit_organizer(organizer: Principal, token: PaymentToken, amount: u64) -> Result<(), TicketingError> {
+    ORGANIZER_BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        let balance = balances.entry((organizer, token)).or_insert(0);
+        if amount > *balance {
+            return Err(TicketingError::InsufficientBalance);
+        }
+        *balance -= amount;
+        Ok(())
+    })
+}
+
+fn record_refund(ticket: &Ticket, amount: u64, reason: RefundReason) {
+    REFUNDS.with(|refunds| {
+        refunds.borrow_mut().push(RefundRecord {
+            ticket_id: ticket.id,
+            event_id: ticket.event_id,
+            recipient: ticket.owner,
+            amount,
+            reason,
+            time: time(),
+        });
+    });
+}
+
+// Pays a ticket's refund to its holder and only then returns the ticket to sale. While
+// the payout is in flight the ticket keeps its seat and inventory but is locked, so it
+// can't be used, moved or refunded again.
+async fn pay_out_refund(event: &Event, ticket: &Ticket, reason: RefundReason) -> Result<u64, TicketingError> {
+    let refund_amount = refund_amount(ticket);
+
+    let locked = REFUNDS_IN_PROGRESS.with(|in_progress| in_progress.borrow_mut().insert(ticket.id));
+    if !locked {
+        return Err(TicketingError::RefundInProgress);
+    }
+
+    if let Err(err) = debit_organizer(event.organizer, event.payment_token, refund_amount) {
+        REFUNDS_IN_PROGRESS.with(|in_progress| in_progress.borrow_mut().remove(&ticket.id));
+        return Err(err);
+    }
+
+    let payment = send_payment(event.payment_token, ticket.owner, refund_amount).await;
+
+    REFUNDS_IN_PROGRESS.with(|in_progress| in_progress.borrow_mut().remove(&ticket.id));
+
+    if let Err(err) = payment {
+        credit_organizer(event.organizer, event.payment_token, refund_amount);
+        return Err(err);
+    }
+
+    let ticket = remove_ticket(ticket.id).ok_or(TicketingError::TicketNotFound)?;
+    record_refund(&ticket, refund_amount, reason);
+    if !event.cancelled {
+        notify_waitlist(event.id, &ticket.tier);
+    }
+
+    Ok(refund_amount)
+}
+
+// Canister methods
+#[init]
+fn init() {
+    // The deployer becomes the canister admin
+    ADMIN.with(|admin| *admin.borrow_mut() = Some(ic_cdk::caller()));
+    schedule_verification_seed();
+    schedule_verification_pubkey();
+    schedule_reservation_sweep();
+    schedule_event_lifecycle_sweep();
+    ic_cdk::println!("Event Ticketing System initialized");
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let state = StableState {
+        events: EVENTS.with(|events| events.take()),
+        tickets: TICKETS.with(|tickets| tickets.take()),
+        purchases: PURCHASES.with(|purchases| purchases.take()),
+        idempotency_keys: IDEMPOTENCY_KEYS.with(|keys| keys.take()),
+        promo_codes: PROMO_CODES.with(|codes| codes.take()),
+        resale_listings: RESALE_LISTINGS.with(|listings| listings.take()),
+        bundles: BUNDLES.with(|bundles| bundles.take()),
+        audit_log: AUDIT_LOG.with(|log| log.take()),
+        ticket_offers: TICKET_OFFERS.with(|offers| offers.take()),
+        reservations: RESERVATIONS.with(|reservations| reservations.take()),
+        waitlists: WAITLISTS.with(|waitlists| waitlists.take()),
+        reviews: REVIEWS.with(|reviews| reviews.take()),
+        refund_requests: REFUND_REQUESTS.with(|requests| requests.take()),
+        refunds: REFUNDS.with(|refunds| refunds.take()),
+        user_profiles: USER_PROFILES.with(|profiles| profiles.take()),
+        user_event_purchases: USER_EVENT_PURCHASES.with(|purchases| purchases.take()),
+        admin: ADMIN.with(|admin| *admin.borrow()),
+        platform_fee_bps: PLATFORM_FEE_BPS.with(|fee| *fee.borrow()),
+        platform_paused: PLATFORM_PAUSED.with(|paused| *paused.borrow()),
+        no_show_penalty: NO_SHOW_PENALTY.with(|penalty| *penalty.borrow()),
+        platform_fee_balance: PLATFORM_FEE_BALANCE.with(|balance| balance.take()),
+        organizer_balances: ORGANIZER_BALANCES.with(|balances| balances.take()),
+        rate_limit_window: RATE_LIMIT_WINDOW.with(|window| *window.borrow()),
+        rate_limit_max_calls: RATE_LIMIT_MAX_CALLS.with(|max_calls| *max_calls.borrow()),
+        event_counter: EVENT_COUNTER.with(|counter| *counter.borrow()),
+        series_counter: SERIES_COUNTER.with(|counter| *counter.borrow()),
+        ticket_counter: TICKET_COUNTER.with(|counter| *counter.borrow()),
+        purchase_counter: PURCHASE_COUNTER.with(|counter| *counter.borrow()),
+        reservation_counter: RESERVATION_COUNTER.with(|counter| *counter.borrow()),
+        refund_request_counter: REFUND_REQUEST_COUNTER.with(|counter| *counter.borrow()),
+        bundle_counter: BUNDLE_COUNTER.with(|counter| *counter.borrow()),
+        verification_seed: VERIFICATION_SEED.with(|seed| seed.take()),
+        verification_nonce: VERIFICATION_NONCE.with(|nonce| *nonce.borrow()),
+    };
+
+    ic_cdk::storage::stable_save((state,))
+        .expect("Failed to save state to stable memory");
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let (state,): (StableState,) = ic_cdk::storage::stable_restore()
+        .expect("Failed to restore state from stable memory");
+
+    EVENTS.with(|events| *events.borrow_mut() = state.events);
+    TICKETS.with(|tickets| *tickets.borrow_mut() = state.tickets);
+    PURCHASES.with(|purchases| *purchases.borrow_mut() = state.purchases);
+    IDEMPOTENCY_KEYS.with(|keys| *keys.borrow_mut() = state.idempotency_keys);
+    PROMO_CODES.with(|codes| *codes.borrow_mut() = state.promo_codes);
+    RESALE_LISTINGS.with(|listings| *listings.borrow_mut() = state.resale_listings);
+    BUNDLES.with(|bundles| *bundles.borrow_mut() = state.bundles);
+    AUDIT_LOG.with(|log| *log.borrow_mut() = state.audit_log);
+    TICKET_OFFERS.with(|offers| *offers.borrow_mut() = state.ticket_offers);
+    RESERVATIONS.with(|reservations| *reservations.borrow_mut() = state.reservations);
+    WAITLISTS.with(|waitlists| *waitlists.borrow_mut() = state.waitlists);
+    REVIEWS.with(|reviews| *reviews.borrow_mut() = state.reviews);
+    REFUND_REQUESTS.with(|requests| *requests.borrow_mut() = state.refund_requests);
+    REFUNDS.with(|refunds| *refunds.borrow_mut() = state.refunds);
+    USER_PROFILES.with(|profiles| *profiles.borrow_mut() = state.user_profiles);
+    USER_EVENT_PURCHASES.with(|purchases| *purchases.borrow_mut() = state.user_event_purchases);
+    ADMIN.with(|admin| *admin.borrow_mut() = state.admin);
+    PLATFORM_FEE_BPS.with(|fee| *fee.borrow_mut() = state.platform_fee_bps);
+    PLATFORM_PAUSED.with(|paused| *paused.borrow_mut() = state.platform_paused);
+    NO_SHOW_PENALTY.with(|penalty| *penalty.borrow_mut() = state.no_show_penalty);
+    PLATFORM_FEE_BALANCE.with(|balance| *balance.borrow_mut() = state.platform_fee_balance);
+    ORGANIZER_BALANCES.with(|balances| *balances.borrow_mut() = state.organizer_balances);
+    RATE_LIMIT_WINDOW.with(|window| *window.borrow_mut() = state.rate_limit_window);
+    RATE_LIMIT_MAX_CALLS.with(|max_calls| *max_calls.borrow_mut() = state.rate_limit_max_calls);
+    EVENT_COUNTER.with(|counter| *counter.borrow_mut() = state.event_counter);
+    SERIES_COUNTER.with(|counter| *counter.borrow_mut() = state.series_counter);
+    TICKET_COUNTER.with(|counter| *counter.borrow_mut() = state.ticket_counter);
+    PURCHASE_COUNTER.with(|counter| *counter.borrow_mut() = state.purchase_counter);
+    RESERVATION_COUNTER.with(|counter| *counter.borrow_mut() = state.reservation_counter);
+    REFUND_REQUEST_COUNTER.with(|counter| *counter.borrow_mut() = state.refund_request_counter);
+    BUNDLE_COUNTER.with(|counter| *counter.borrow_mut() = state.bundle_counter);
+    VERIFICATION_SEED.with(|seed| *seed.borrow_mut() = state.verification_seed);
+    VERIFICATION_NONCE.with(|nonce| *nonce.borrow_mut() = state.verification_nonce);
+
+    // Rebuild derived indexes
+    EVENTS.with(|events| {
+        ORGANIZER_EVENTS.with(|index| {
+            let mut index = index.borrow_mut();
+            for event in events.borrow().values() {
+                index.entry(event.organizer).or_default().push(event.id);
+            }
+        });
+
+        for event in events.borrow().values().filter(|event| !event.archived) {
+            index_event_name(event.id, &event.name);
+        }
+    });
+
+    TICKETS.with(|tickets| {
+        VERIFICATION_CODES.with(|codes| {
+            *codes.borrow_mut() = tickets.borrow().values()
+                .map(|ticket| (ticket.verification_code.clone(), ticket.id))
+                .collect();
+        });
+
+        TAKEN_SEATS.with(|taken| {
+            let mut taken = taken.borrow_mut();
+            for ticket in tickets.borrow().values() {
+                taken.entry(ticket.event_id).or_default().insert(ticket.seat_number.clone());
+            }
+        });
+
+        for ticket in tickets.borrow().values() {
+            adjust_owned_tickets(ticket.owner, ticket.event_id, 1);
+        }
+    });
+
+    // Seats and tickets held by outstanding reservations are taken too
+    RESERVATIONS.with(|reservations| {
+        for reservation in reservations.borrow().values() {
+            let hold = &reservation.hold;
+            take_seats(hold.event_id, &hold.seats);
+            adjust_owned_tickets(hold.buyer, hold.event_id, hold.quantity as i64);
+        }
+    });
+
+    schedule_verification_seed();
+    schedule_verification_pubkey();
+    schedule_reservation_sweep();
+    schedule_event_lifecycle_sweep();
+
+    ic_cdk::println!("Event Ticketing System upgraded");
+}
+
+#[update]
+#[allow(clippy::too_many_arguments)]
+fn create_event(
+    name: String,
+    description: String,
+    venue: String,
+    date: u64,
+    total_tickets: u32,
+    price_icp: u64,
+    max_tickets_per_user: u32,
+    sale_start_time: u64,
+    sale_end_time: u64,
+    refund_deadline: Option<u64>,
+    tiers: Option<Vec<TicketTier>>,
+    category: Option<String>,
+    requires_verified_buyers: Option<bool>,
+    seat_map: Option<Vec<String>>,
+    media: Option<EventMedia>,
+    notify_canister: Option<Principal>,
+) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    require_authenticated(caller)?;
+    require_not_paused()?;
+    check_rate_limit(caller, current_time)?;
+
+    let EventMedia { image_url, metadata_url } = media.unwrap_or_default();
+
+    let input = EventInput {
+        name,
+        description,
+        venue,
+        date,
+        total_tickets,
+        price_icp,
+        max_tickets_per_user,
+        presale_start_time: None,
+        sale_start_time,
+        sale_end_time,
+        auto_extend: None,
+        auto_extend_max: None,
+        refund_deadline,
+        tiers,
+        category,
+        requires_verified_buyers,
+        min_reputation: None,
+        seat_map,
+        seat_numbering: None,
+        custom_fields: None,
+        allowlist: None,
+        price_schedule: None,
+        transferable: None,
+        image_url,
+        metadata_url,
+        allow_venue_overlap: None,
+        notify_canister,
+    };
+
+    let event = build_event(caller, input, current_time)?;
+    Ok(insert_event(event))
+}
+
+// Like `create_event`, but takes every setting an event can start out with, e.g. seat
+// numbering, a price schedule or an allowlist
+#[update]
+fn create_event_with(input: EventInput) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    require_authenticated(caller)?;
+    require_not_paused()?;
+    check_rate_limit(caller, current_time)?;
+
+    let event = build_event(caller, input, current_time)?;
+    Ok(insert_event(event))
+}
+
+// Creates one event per date from `base`, shifting its date, sale window and refund
+// deadline along with each occurrence. Nothing is created unless every occurrence is valid.
+#[update]
+fn create_event_series(base: EventInput, dates: Vec<u64>) -> Result<Vec<u64>, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    require_authenticated(caller)?;
+    require_not_paused()?;
+    check_rate_limit(caller, current_time)?;
+
+    if dates.is_empty() || dates.len() > MAX_BATCH_SIZE {
+        return Err(TicketingError::InvalidQuantity);
+    }
+
+    if !base.allow_venue_overlap.unwrap_or(false) {
+        check_series_dates_apart(&dates)?;
+    }
+
+    let events = dates.iter()
+        .map(|date| {
+            let shift = |time: u64| {
+                time.checked_add(*date)
+                    .and_then(|time| time.checked_sub(base.date))
+                    .ok_or(TicketingError::InvalidTimeWindow)
+            };
+
+            let input = EventInput {
+                date: *date,
+                presale_start_time: base.presale_start_time.map(shift).transpose()?,
+                sale_start_time: shift(base.sale_start_time)?,
+                sale_end_time: shift(base.sale_end_time)?,
+                refund_deadline: base.refund_deadline.map(shift).transpose()?,
+                ..base.clone()
+            };
+            build_event(caller, input, current_time)
+        })
+        .collect::<Result<Vec<Event>, TicketingError>>()?;
+
+    let series_id = SERIES_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        *counter += 1;
+        *counter
+    });
+
+    Ok(events.into_iter()
+        .map(|event| insert_event(Event { series_id: Some(series_id), ..event }))
+        .collect())
+}
+
+#[update]
+#[allow(clippy::too_many_arguments)]
+fn update_event(
+    event_id: u64,
+    name: String,
+    description: String,
+    venue: String,
+    date: u64,
+    total_tickets: u32,
+    price_icp: u64,
+    max_tickets_per_user: u32,
+    sale_start_time: u64,
+    sale_end_time: u64,
+    refund_deadline: Option<u64>,
+    tiers: Option<Vec<TicketTier>>,
+    category: Option<String>,
+    requires_verified_buyers: Option<bool>,
+    seat_map: Option<Vec<String>>,
+) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if current_time >= event.sale_start_time {
+            return Err(TicketingError::SaleAlreadyStarted);
+        }
+
+        validate_event_text(&name, &description, &venue)?;
+        validate_time_window(date, sale_start_time, sale_end_time, current_time)?;
+        validate_presale_start(event.presale_start_time, sale_start_time)?;
+
+        let mut tiers = build_tiers(total_tickets, price_icp, tiers)?;
+
+        // Carry over what each tier has already handed out; a tier can't shrink
+        // below its sales or be dropped while it has tickets outstanding
+        for old_tier in &event.tiers {
+            let sold = old_tier.total - old_tier.available;
+            if sold == 0 {
+                continue;
+            }
+
+            let tier = tiers.iter_mut()
+                .find(|tier| tier.name == old_tier.name)
+                .ok_or(TicketingError::InsufficientTickets)?;
+
+            if tier.total < sold {
+                return Err(TicketingError::InsufficientTickets);
+            }
+            tier.available = tier.total - sold;
+        }
+
+        let total_tickets = tiers.iter().map(|tier| tier.total).sum();
+        let available_tickets = tiers.iter().map(|tier| tier.available).sum();
+
+        // Shrinking the event mustn't leave only the reserved pool, which would quietly
+        // sell it out to the public
+        if event.reserved_tickets > 0 && available_tickets <= event.reserved_tickets {
+            return Err(TicketingError::InsufficientTickets);
+        }
+
+        let seat_map = seat_map.unwrap_or_else(|| event.seat_map.clone());
+        validate_seat_map(&seat_map, total_tickets)?;
+        validate_seat_numbering(event.seat_numbering, total_tickets)?;
+
+        if !event.archived {
+            unindex_event_name(event_id, &event.name);
+            index_event_name(event_id, &name);
+        }
+        event.name = name;
+        event.description = description;
+        event.venue = venue;
+        event.date = date;
+        event.total_tickets = total_tickets;
+        event.available_tickets = available_tickets;
+        event.price_icp = tiers[0].price_icp;
+        event.tiers = tiers;
+        event.seat_map = seat_map;
+        event.max_tickets_per_user = max_tickets_per_user;
+        event.sale_start_time = sale_start_time;
+        event.sale_end_time = sale_end_time;
+        event.auto_extended = 0;
+        event.refund_deadline = refund_deadline.unwrap_or(date);
+        if let Some(category) = category {
+            event.category = category;
+        }
+        if let Some(requires_verified_buyers) = requires_verified_buyers {
+            event.requires_verified_buyers = requires_verified_buyers;
+        }
+        record_audit(caller, "Updated event details", Some(event_id));
+        Ok(())
+    })
+}
+
+// Moves the event to a new date. Every current holder has to acknowledge the change
+// and can refund instead within RESCHEDULE_GRACE_PERIOD.
+#[update]
+fn reschedule_event(event_id: u64, new_date: u64, new_sale_end_time: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if event.cancelled {
+            return Err(TicketingError::EventCancelled);
+        }
+
+        validate_time_window(new_date, event.sale_start_time, new_sale_end_time, current_time)?;
+
+        event.previous_date = Some(event.date);
+        event.rescheduled_at = Some(current_time);
+        event.date = new_date;
+        event.sale_end_time = new_sale_end_time;
+        event.auto_extended = 0;
+        event.completed = false;
+        Ok(())
+    })?;
+
+    TICKETS.with(|tickets| {
+        for ticket in tickets.borrow_mut().values_mut() {
+            if ticket.event_id == event_id && !ticket.is_used {
+                ticket.needs_reacknowledgement = true;
+            }
+        }
+    });
+
+    record_audit(caller, format!("Rescheduled event to {}", new_date), Some(event_id));
+    Ok(())
+}
+
+#[update]
+fn acknowledge_reschedule(ticket_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    TICKETS.with(|tickets| {
+        let mut tickets = tickets.borrow_mut();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or(TicketingError::TicketNotFound)?;
+
+        if ticket.owner != caller {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        ticket.needs_reacknowledgement = false;
+        Ok(())
+    })
+}
+
+#[update]
+fn set_event_media(
+    event_id: u64,
+    image_url: String,
+    metadata_url: Option<String>,
+) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    validate_url(&image_url)?;
+    if let Some(url) = &metadata_url {
+        validate_url(url)?;
+    }
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.image_url = image_url;
+        event.metadata_url = metadata_url;
+        Ok(())
+    })
+}
+
+// Bounds on how many tickets a single order may contain, e.g. a minimum of 2 for table seating
+#[update]
+fn set_purchase_limits(
+    event_id: u64,
+    min_tickets_per_purchase: u32,
+    max_tickets_per_purchase: u32,
+) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    if min_tickets_per_purchase == 0 || min_tickets_per_purchase > max_tickets_per_purchase {
+        return Err(TicketingError::InvalidQuantity);
+    }
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.min_tickets_per_purchase = min_tickets_per_purchase;
+        event.max_tickets_per_purchase = max_tickets_per_purchase;
+        Ok(())
+    })
+}
+
+#[update]
+fn set_event_notify_canister(event_id: u64, notify_canister: Option<Principal>) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.notify_canister = notify_canister;
+        Ok(())
+    })
+}
+
+// When set, holders have to go through `request_refund` and wait for the organizer;
+// cancelled and rescheduled events stay refundable without approval
+#[update]
+fn set_refunds_require_approval(event_id: u64, required: bool) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.refunds_require_approval = required;
+        Ok(())
+    })
+}
+
+// Stops check-ins `window` nanoseconds after the event starts; None accepts them indefinitely
+#[update]
+fn set_check_in_window(event_id: u64, window: Option<u64>) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.check_in_window = window;
+        Ok(())
+    })
+}
+
+// Attendee details buyers have to fill in for each purchase, e.g. "Dietary needs".
+// Tickets already sold keep the values they were bought with; tickets the buyer isn't
+// asked about (comps, waitlist holds, free claims) carry none.
+#[update]
+fn set_custom_fields(event_id: u64, fields: Vec<String>) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    validate_custom_fields(&fields)?;
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.custom_fields = fields;
+        Ok(())
+    })
+}
+
+// Turns away buyers whose reputation score is below `min_reputation`; None lifts the gate.
+// New users start at INITIAL_REPUTATION, so anything above it admits only established attendees.
+#[update]
+fn set_min_reputation(event_id: u64, min_reputation: Option<u32>) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.min_reputation = min_reputation;
+        Ok(())
+    })
+}
+
+// Pushes `sale_end_time` back by `step` nanoseconds whenever the sale closes with tickets
+// still on public sale, until the event date or `max` nanoseconds past the scheduled end;
+// None lets the sale end as scheduled
+#[update]
+fn set_auto_extend(event_id: u64, step: Option<u64>, max: Option<u64>) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    validate_auto_extend(step, max)?;
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.auto_extend = step;
+        event.auto_extend_max = max;
+        Ok(())
+    })
+}
+
+// Opens the sale early for allowlisted buyers; None goes back to a single public sale.
+// While a presale is set the allowlist no longer restricts the public sale.
+#[update]
+fn set_presale_start_time(event_id: u64, presale_start_time: Option<u64>) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        validate_presale_start(presale_start_time, event.sale_start_time)?;
+
+        event.presale_start_time = presale_start_time;
+        Ok(())
+    })
+}
+
+// Restricts sales to `allowlist` for presales and members-only drops; None reopens the sale to everyone
+#[update]
+fn set_event_allowlist(event_id: u64, allowlist: Option<Vec<Principal>>) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.allowlist = allowlist;
+        Ok(())
+    })
+}
+
+// Turning transfers off also stops tickets already listed for resale from selling
+#[update]
+fn set_event_transferable(event_id: u64, transferable: bool) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.transferable = transferable;
+        Ok(())
+    })
+}
+
+// Lets tickets resell for up to `cap_bps` of what they cost, e.g. 12_000 for 120%; None
+// goes back to capping at what they cost. Existing listings keep their price.
+#[update]
+fn set_resale_cap(event_id: u64, cap_bps: Option<u32>) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.resale_cap_bps = cap_bps;
+        Ok(())
+    })
+}
+
+// Approximate fiat price shown next to the real one, e.g. ("USD", 2500) for $25.00.
+// Purely informational: purchases are always charged `price_icp` in the payment token.
+#[update]
+fn set_display_price(event_id: u64, currency: String, amount: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(TicketingError::InvalidCurrency);
+    }
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.display_price = Some((currency.to_ascii_uppercase(), amount));
+        Ok(())
+    })
+}
+
+// Early-bird style price steps for the default tier; None goes back to its fixed price
+#[update]
+fn set_price_schedule(event_id: u64, schedule: Option<Vec<PriceStep>>) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let schedule = schedule.map(validate_price_schedule).transpose()?.unwrap_or_default();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.price_schedule = schedule;
+        record_audit(caller, "Changed price schedule", Some(event_id));
+        Ok(())
+    })
+}
+
+// None goes back to the default of 10% of the event's tickets
+#[update]
+fn set_low_stock_threshold(event_id: u64, threshold: Option<u32>) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.low_stock_threshold = threshold;
+        Ok(())
+    })
+}
+
+// Changes how seats are assigned for an event without a seat map; only before the sale opens
+#[update]
+fn set_seat_numbering(event_id: u64, seat_numbering: SeatNumbering) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if current_time >= event.sale_start_time {
+            return Err(TicketingError::SaleAlreadyStarted);
+        }
+
+        validate_seat_numbering(seat_numbering, event.total_tickets)?;
+
+        event.seat_numbering = seat_numbering;
+        Ok(())
+    })
+}
+
+// Switches the token the event is priced and paid in; only before the sale opens
+#[update]
+fn set_event_payment_token(event_id: u64, payment_token: PaymentToken) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if current_time >= event.sale_start_time {
+            return Err(TicketingError::SaleAlreadyStarted);
+        }
+
+        event.payment_token = payment_token;
+        Ok(())
+    })
+}
+
+// Withholds `reserved_tickets` of the unsold tickets from public sale for comps
+#[update]
+fn set_reserved_tickets(event_id: u64, reserved_tickets: u32) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if reserved_tickets > event.available_tickets {
+            return Err(TicketingError::InsufficientTickets);
+        }
+
+        event.reserved_tickets = reserved_tickets;
+        Ok(())
+    })
+}
+
+#[update]
+fn create_promo_code(
+    event_id: u64,
+    code: String,
+    discount_bps: u16,
+    max_uses: u32,
+    expires_at: Option<u64>,
+) -> Result<(), TicketingError> {
+    let event = get_event(event_id)?;
+
+    if !event.is_event_admin(ic_cdk::caller()) {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    if code.trim().is_empty() || discount_bps == 0 || discount_bps > 10_000 || max_uses == 0 {
+        return Err(TicketingError::PromoCodeInvalid);
+    }
+
+    let code = code.to_uppercase();
+    let promo = PromoCode {
+        code: code.clone(),
+        event_id,
+        discount_bps,
+        max_uses,
+        remaining_uses: max_uses,
+        expires_at,
+    };
+
+    PROMO_CODES.with(|codes| {
+        codes.borrow_mut().insert((event_id, code), promo);
+    });
+
+    Ok(())
+}
+
+#[query]
+fn get_event(event_id: u64) -> Result<Event, TicketingError> {
+    let current_time = time();
+    EVENTS.with(|events| {
+        events.borrow().get(&event_id)
+            .map(|event| refreshed(event, current_time))
+            .ok_or(TicketingError::EventNotFound)
+    })
+}
+
+// Looks up several events at once; ids past MAX_BATCH_SIZE are ignored
+#[query]
+fn get_events_batch(ids: Vec<u64>) -> Vec<Result<Event, TicketingError>> {
+    ids.into_iter()
+        .take(MAX_BATCH_SIZE)
+        .map(get_event)
+        .collect()
+}
+
+#[query]
+fn get_all_events() -> Vec<Event> {
+    let current_time = time();
+    EVENTS.with(|events| {
+        events.borrow().values()
+            .filter(|event| !event.archived)
+            .map(|event| refreshed(event, current_time))
+            .collect()
+    })
+}
+
+#[query]
+fn get_events_paginated(offset: u64, limit: u64) -> (Vec<Event>, u64) {
+    let current_time = time();
+    let limit = limit.min(MAX_PAGE_SIZE) as usize;
+    EVENTS.with(|events| {
+        let events = events.borrow();
+        let listed = || events.values().filter(|event| !event.archived);
+        let page = listed()
+            .skip(offset as usize)
+            .take(limit)
+            .map(|event| refreshed(event, current_time))
+            .collect();
+        (page, listed().count() as u64)
+    })
+}
+
+#[query]
+fn get_active_events() -> Vec<Event> {
+    let current_time = time();
+    EVENTS.with(|events| {
+        events.borrow().values()
+            .filter(|event| event.is_active && !event.archived && event.sale_end_time > current_time)
+            .map(|event| refreshed(event, current_time))
+            .collect()
+    })
+}
+
+// Events still taking place dated within `[start, end]`, in date order, for calendar views.
+// Events whose sale has closed stay on the calendar; cancelled and archived ones don't.
+#[query]
+fn get_events_in_range(start: u64, end: u64) -> Vec<Event> {
+    let current_time = time();
+    let mut events: Vec<Event> = EVENTS.with(|events| {
+        events.borrow().values()
+            .filter(|event| !event.cancelled && !event.archived)
+            .filter(|event| (start..=end).contains(&event.date))
+            .map(|event| refreshed(event, current_time))
+            .collect()
+    });
+
+    events.sort_by_key(|event| event.date);
+    events
+}
+
+#[query]
+fn get_archived_events() -> Vec<Event> {
+    let current_time = time();
+    EVENTS.with(|events| {
+        events.borrow().values()
+            .filter(|event| event.archived)
+            .map(|event| refreshed(event, current_time))
+            .collect()
+    })
+}
+
+#[query]
+fn get_events_by_category(category: String) -> Vec<Event> {
+    let current_time = time();
+    EVENTS.with(|events| {
+        events.borrow().values()
+            .filter(|event| event.category.eq_ignore_ascii_case(&category))
+            .map(|event| refreshed(event, current_time))
+            .collect()
+    })
+}
+
+#[query]
+fn get_events_by_organizer(organizer: Principal) -> Vec<Event> {
+    let current_time = time();
+    let event_ids = ORGANIZER_EVENTS.with(|index| {
+        index.borrow().get(&organizer).cloned().unwrap_or_default()
+    });
+
+    EVENTS.with(|events| {
+        let events = events.borrow();
+        event_ids.iter()
+            .filter_map(|event_id| events.get(event_id).map(|event| refreshed(event, current_time)))
+            .collect()
+    })
+}
+
+// Every event `principal` organizes, co-organizes or staffs, with how they're attached
+#[query]
+fn get_managed_events(principal: Principal) -> Vec<(Event, EventAccess)> {
+    let current_time = time();
+    EVENTS.with(|events| {
+        events.borrow().values()
+            .filter_map(|event| {
+                event.access(principal).map(|access| (refreshed(event, current_time), access))
+            })
+            .collect()
+    })
+}
+
+// Every occurrence of a recurring event, in date order
+#[query]
+fn get_series_events(series_id: u64) -> Vec<Event> {
+    let current_time = time();
+    let mut events: Vec<Event> = EVENTS.with(|events| {
+        events.borrow().values()
+            .filter(|event| event.series_id == Some(series_id))
+            .map(|event| refreshed(event, current_time))
+            .collect()
+    });
+
+    events.sort_by_key(|event| event.date);
+    events
+}
+
+// Case-insensitive substring match against name, description and venue
+#[query]
+fn search_events(query: String) -> Vec<Event> {
+    let current_time = time();
+    let query = query.to_lowercase();
+    EVENTS.with(|events| {
+        events.borrow().values()
+            .filter(|event| {
+                event.name.to_lowercase().contains(&query)
+                    || event.description.to_lowercase().contains(&query)
+                    || event.venue.to_lowercase().contains(&query)
+            })
+            .map(|event| refreshed(event, current_time))
+            .collect()
+    })
+}
+
+// Event ids and names for a search-as-you-type box. Names starting with `prefix` come
+// before names with a later word starting with it; ties are alphabetical.
+#[query]
+fn autocomplete_events(prefix: String, limit: u64) -> Vec<(u64, String)> {
+    let prefix = prefix.trim().to_lowercase();
+    if prefix.is_empty() {
+        return Vec::new();
+    }
+
+    let event_ids: BTreeSet<u64> = EVENT_NAME_INDEX.with(|index| {
+        index.borrow().range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .flat_map(|(_, event_ids)| event_ids.iter().copied())
+            .collect()
+    });
+
+    let mut matches: Vec<(bool, String, u64, String)> = EVENTS.with(|events| {
+        let events = events.borrow();
+        event_ids.into_iter()
+            .filter_map(|event_id| events.get(&event_id))
+            .map(|event| {
+                let lowercase = event.name.to_lowercase();
+                (!lowercase.starts_with(&prefix), lowercase, event.id, event.name.clone())
+            })
+            .collect()
+    });
+
+    matches.sort();
+    matches.into_iter()
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(|(_, _, event_id, name)| (event_id, name))
+        .collect()
+}
+
+// How many more tickets `user` may buy before hitting the event's per-user limit
+#[query]
+fn get_remaining_allowance(event_id: u64, user: Principal) -> Result<u32, TicketingError> {
+    let event = get_event(event_id)?;
+    Ok(event.max_tickets_per_user.saturating_sub(tickets_counted_against_limit(user, event_id)))
+}
+
+// "From" price for listing cards: the cheapest current price among tiers with tickets
+// left. Sold-out events return InsufficientTickets rather than a stale price.
+#[query]
+fn get_starting_price(event_id: u64) -> Result<u64, TicketingError> {
+    let current_time = time();
+    let event = get_event(event_id)?;
+
+    if event.available_tickets <= event.reserved_tickets {
+        return Err(TicketingError::InsufficientTickets);
+    }
+
+    event.tiers.iter()
+        .filter(|tier| tier.available > 0)
+        .map(|tier| event.tier_price(tier, current_time))
+        .min()
+        .ok_or(TicketingError::InsufficientTickets)
+}
+
+#[query]
+fn get_inventory_status(event_id: u64) -> Result<InventoryStatus, TicketingError> {
+    get_event(event_id).map(|event| inventory_status(&event))
+}
+
+#[query]
+fn get_available_seats(event_id: u64) -> Vec<String> {
+    let Ok(event) = get_event(event_id) else {
+        return Vec::new();
+    };
+
+    TAKEN_SEATS.with(|taken| {
+        let taken = taken.borrow();
+        let event_taken = taken.get(&event_id);
+        event.seat_map.into_iter()
+            .filter(|seat| !event_taken.is_some_and(|taken| taken.contains(seat)))
+            .collect()
+    })
+}
+
+// Dry run of `purchase_tickets`: returns the amount that would be charged. As a query
+// it sees the caller's rate limit without using it up.
+#[query]
+fn can_purchase(
+    event_id: u64,
+    quantity: u32,
+    tier_name: Option<String>,
+    seats: Option<Vec<String>>,
+    promo_code: Option<String>,
+    metadata: Option<HashMap<String, String>>,
+) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    check_purchase_caller(caller, current_time)?;
+
+    let request = PurchaseRequest {
+        event_id,
+        quantity,
+        tier_name,
+        seats: seats.unwrap_or_default(),
+        promo_code,
+        allow_partial: false,
+        metadata: Some(metadata.unwrap_or_default()),
+    };
+
+    validate_purchase(caller, &request, current_time)
+        .map(|quote| quote.total_amount)
+}
+
+// Retrying with the same `idempotency_key` returns the original purchase instead
+// of charging the caller again
+#[update]
+#[allow(clippy::too_many_arguments)]
+async fn purchase_tickets(
+    event_id: u64,
+    quantity: u32,
+    tier_name: Option<String>,
+    seats: Option<Vec<String>>,
+    promo_code: Option<String>,
+    idempotency_key: Option<String>,
+    allow_partial: Option<bool>,
+    metadata: Option<HashMap<String, String>>,
+) -> Result<Purchase, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let request = PurchaseRequest {
+        event_id,
+        quantity,
+        tier_name,
+        seats: seats.unwrap_or_default(),
+        promo_code,
+        allow_partial: allow_partial.unwrap_or(false),
+        metadata: Some(metadata.unwrap_or_default()),
+    };
+
+    let Some(idempotency_key) = idempotency_key else {
+        return execute_purchase(caller, request).await;
+    };
+
+    if idempotency_key.is_empty() || idempotency_key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(TicketingError::InvalidIdempotencyKey);
+    }
+
+    let key = (caller, idempotency_key);
+    if let Some(purchase_id) = IDEMPOTENCY_KEYS.with(|keys| keys.borrow().get(&key).copied()) {
+        return PURCHASES.with(|purchases| {
+            purchases.borrow().get(&purchase_id)
+                .cloned()
+                .ok_or(TicketingError::PurchaseNotFound)
+        });
+    }
+
+    // A retry that arrives while the original is still paying must not start a second purchase
+    if !PENDING_IDEMPOTENCY_KEYS.with(|pending| pending.borrow_mut().insert(key.clone())) {
+        return Err(TicketingError::PurchaseInProgress);
+    }
+
+    let result = execute_purchase(caller, request).await;
+
+    PENDING_IDEMPOTENCY_KEYS.with(|pending| pending.borrow_mut().remove(&key));
+    if let Ok(purchase) = &result {
+        IDEMPOTENCY_KEYS.with(|keys| keys.borrow_mut().insert(key, purchase.id));
+    }
+
+    result
+}
+
+async fn execute_purchase(caller: Principal, request: PurchaseRequest) -> Result<Purchase, TicketingError> {
+    let current_time = time();
+
+    check_purchase_caller(caller, current_time)?;
+
+    // Make sure the tickets can be given codes before any money moves
+    ensure_verification_seed().await;
+
+    // Hold the inventory while the ledger call is in flight so concurrent
+    // purchases can't oversell the event
+    let hold = place_hold(caller, request, current_time)?;
+
+    if let Err(err) = collect_payment(hold.payment_token, caller, ic_cdk::id(), hold.total_amount).await {
+        release_hold(&hold);
+        return Err(err);
+    }
+
+    Ok(complete_purchase(hold, current_time))
+}
+
+// Holds tickets for the caller for RESERVATION_TTL without charging them yet.
+// Pay with `confirm_reservation` or give the tickets back with `release_reservation`.
+#[update]
+fn reserve_tickets(
+    event_id: u64,
+    quantity: u32,
+    tier_name: Option<String>,
+    seats: Option<Vec<String>>,
+    promo_code: Option<String>,
+    metadata: Option<HashMap<String, String>>,
+) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    check_purchase_caller(caller, current_time)?;
+
+    let request = PurchaseRequest {
+        event_id,
+        quantity,
+        tier_name,
+        seats: seats.unwrap_or_default(),
+        promo_code,
+        allow_partial: false,
+        metadata: Some(metadata.unwrap_or_default()),
+    };
+
+    let hold = place_hold(caller, request, current_time)?;
+
+    let reservation_id = RESERVATION_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        *counter += 1;
+        *counter
+    });
+
+    let reservation = Reservation {
+        id: reservation_id,
+        hold,
+        expires_at: current_time + RESERVATION_TTL,
+        from_waitlist: false,
+    };
+
+    RESERVATIONS.with(|reservations| {
+        reservations.borrow_mut().insert(reservation_id, reservation);
+    });
+
+    Ok(reservation_id)
+}
+
+#[update]
+async fn confirm_reservation(reservation_id: u64) -> Result<Purchase, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    require_not_paused()?;
+
+    ensure_verification_seed().await;
+
+    let current_time = time();
+
+    // Take the reservation out so the expiry sweep can't release it mid-payment
+    let reservation = RESERVATIONS.with(|reservations| {
+        let mut reservations = reservations.borrow_mut();
+        let reservation = reservations.get(&reservation_id)
+            .ok_or(TicketingError::ReservationNotFound)?;
+
+        if reservation.hold.buyer != caller {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if current_time > reservation.expires_at {
+            return Err(TicketingError::ReservationExpired);
+        }
+
+        Ok(reservations.remove(&reservation_id).expect("Reservation was just found"))
+    })?;
+
+    let hold = &reservation.hold;
+    if let Err(err) = collect_payment(hold.payment_token, caller, ic_cdk::id(), hold.total_amount).await {
+        // Keep the hold so the buyer can retry before it expires
+        RESERVATIONS.with(|reservations| {
+            reservations.borrow_mut().insert(reservation_id, reservation);
+        });
+        return Err(err);
+    }
+
+    Ok(complete_purchase(reservation.hold, current_time))
+}
+
+// Pays for the ticket held for the caller when they reached the head of the waitlist
+#[update]
+async fn claim_waitlist_reservation(event_id: u64) -> Result<Purchase, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let reservation_id = RESERVATIONS.with(|reservations| {
+        reservations.borrow().values()
+            .find(|reservation| {
+                reservation.from_waitlist
+                    && reservation.hold.buyer == caller
+                    && reservation.hold.event_id == event_id
+            })
+            .map(|reservation| reservation.id)
+            .ok_or(TicketingError::ReservationNotFound)
+    })?;
+
+    confirm_reservation(reservation_id).await
+}
+
+#[update]
+fn release_reservation(reservation_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let reservation = RESERVATIONS.with(|reservations| {
+        let mut reservations = reservations.borrow_mut();
+        let reservation = reservations.get(&reservation_id)
+            .ok_or(TicketingError::ReservationNotFound)?;
+
+        if reservation.hold.buyer != caller {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        Ok(reservations.remove(&reservation_id).expect("Reservation was just found"))
+    })?;
+
+    release_hold(&reservation.hold);
+    if !reservation.from_waitlist {
+        notify_waitlist(reservation.hold.event_id, &reservation.hold.tier_name);
+    }
+
+    Ok(())
+}
+
+// RSVP for a free event: one ticket per user, whatever the event's per-user limit,
+// with no ledger interaction
+#[update]
+async fn claim_free_ticket(event_id: u64) -> Result<Ticket, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    check_purchase_caller(caller, current_time)?;
+
+    ensure_verification_seed().await;
+
+    let event = get_event(event_id)?;
+
+    if event.price_icp != 0 {
+        return Err(TicketingError::EventNotFree);
+    }
+
+    let already_claimed = USER_EVENT_PURCHASES.with(|purchases| {
+        purchases.borrow().get(&(caller, event_id)).is_some_and(|count| *count > 0)
+    }) || USER_EVENT_TICKETS.with(|owned| {
+        owned.borrow().get(&(caller, event_id)).is_some_and(|count| *count > 0)
+    });
+    if already_claimed {
+        return Err(TicketingError::AlreadyClaimed);
+    }
+
+    let request = PurchaseRequest {
+        event_id,
+        quantity: 1,
+        tier_name: None,
+        seats: assign_free_seat(&event)?,
+        promo_code: None,
+        allow_partial: false,
+        metadata: None,
+    };
+
+    let hold = place_hold(caller, request, current_time)?;
+    let purchase = complete_purchase(hold, current_time);
+
+    // Kept apart from paid purchases so sales figures only count sales
+    PURCHASES.with(|purchases| {
+        if let Some(purchase) = purchases.borrow_mut().get_mut(&purchase.id) {
+            purchase.is_free = true;
+        }
+    });
+
+    TICKETS.with(|tickets| {
+        purchase.ticket_ids.first()
+            .and_then(|ticket_id| tickets.borrow().get(ticket_id).cloned())
+            .ok_or(TicketingError::TicketNotFound)
+    })
+}
+
+// Packages several of the caller's events as one pass. The events must all be
+// priced in the same token and none of them may be cancelled.
+#[update]
+fn create_bundle(name: String, event_ids: Vec<u64>, price_icp: u64) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    require_not_paused()?;
+
+    let unique: BTreeSet<&u64> = event_ids.iter().collect();
+    if name.trim().is_empty() || event_ids.is_empty() || event_ids.len() > MAX_BATCH_SIZE
+        || unique.len() != event_ids.len()
+    {
+        return Err(TicketingError::InvalidBundle);
+    }
+
+    let mut payment_token = None;
+    for event_id in &event_ids {
+        let event = get_event(*event_id)?;
+
+        if event.organizer != caller {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if event.cancelled {
+            return Err(TicketingError::EventCancelled);
+        }
+
+        if payment_token.is_some_and(|token| token != event.payment_token) {
+            return Err(TicketingError::InvalidBundle);
+        }
+        payment_token = Some(event.payment_token);
+    }
+
+    let bundle_id = BUNDLE_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        *counter += 1;
+        *counter
+    });
+
+    let bundle = Bundle {
+        id: bundle_id,
+        name,
+        organizer: caller,
+        event_ids,
+        price_icp,
+        payment_token: payment_token.unwrap_or_default(),
+        created_at: current_time,
+    };
+
+    BUNDLES.with(|bundles| {
+        bundles.borrow_mut().insert(bundle_id, bundle);
+    });
+
+    Ok(bundle_id)
+}
+
+// A bundle is paid in one token, so every event in it must still be priced in that token
+fn check_bundle_token(bundle_token: PaymentToken, event_tokens: &[PaymentToken]) -> Result<(), TicketingError> {
+    if event_tokens.iter().any(|token| *token != bundle_token) {
+        return Err(TicketingError::InvalidBundle);
+    }
+
+    Ok(())
+}
+
+#[query]
+fn get_bundle(bundle_id: u64) -> Result<Bundle, TicketingError> {
+    BUNDLES.with(|bundles| {
+        bundles.borrow().get(&bundle_id)
+            .cloned()
+            .ok_or(TicketingError::BundleNotFound)
+    })
+}
+
+// Buys one ticket to every event in the bundle for a single payment. Each event's own
+// purchase rules apply; if any event can't sell the caller a ticket, nothing is bought.
+// The price is split evenly across the events so each ticket refunds its share.
+#[update]
+async fn purchase_bundle(bundle_id: u64) -> Result<Vec<Ticket>, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    check_purchase_caller(caller, current_time)?;
+
+    ensure_verification_seed().await;
+
+    let bundle = get_bundle(bundle_id)?;
+
+    let mut holds: Vec<PurchaseHold> = Vec::new();
+    for event_id in &bundle.event_ids {
+        let hold = get_event(*event_id).and_then(|event| {
+            let request = PurchaseRequest {
+                event_id: *event_id,
+                quantity: 1,
+                tier_name: None,
+                seats: assign_free_seat(&event)?,
+                promo_code: None,
+                allow_partial: false,
+                metadata: None,
+            };
+            place_hold(caller, request, current_time)
+        });
+
+        match hold {
+            Ok(hold) => holds.push(hold),
+            Err(err) => {
+                holds.iter().for_each(release_hold);
+                return Err(err);
+            }
+        }
+    }
+
+    // An event may have switched tokens since the bundle was created
+    let hold_tokens: Vec<PaymentToken> = holds.iter().map(|hold| hold.payment_token).collect();
+    if let Err(err) = check_bundle_token(bundle.payment_token, &hold_tokens) {
+        holds.iter().for_each(release_hold);
+        return Err(err);
+    }
+
+    if let Err(err) = collect_payment(bundle.payment_token, caller, ic_cdk::id(), bundle.price_icp).await {
+        holds.iter().for_each(release_hold);
+        return Err(err);
+    }
+
+    // Any remainder of the split goes to the first event
+    let share = bundle.price_icp / holds.len() as u64;
+    let remainder = bundle.price_icp % holds.len() as u64;
+
+    let ticket_ids: Vec<u64> = holds.into_iter()
+        .enumerate()
+        .flat_map(|(index, hold)| {
+            let total_amount = if index == 0 { share + remainder } else { share };
+            complete_purchase(PurchaseHold { total_amount, ..hold }, current_time).ticket_ids
+        })
+        .collect();
+
+    TICKETS.with(|tickets| {
+        let tickets = tickets.borrow();
+        Ok(ticket_ids.iter()
+            .filter_map(|ticket_id| tickets.get(ticket_id).cloned())
+            .collect())
+    })
+}
+
+// Takes one ticket for a comp out of the inventory. `seat` is checked like a buyer's
+// request; without one the first free seat is used.
+fn place_comp_hold(
+    organizer: Principal,
+    event_id: u64,
+    to: Principal,
+    seat: Option<String>,
+    from_reserved_pool: bool,
+) -> Result<PurchaseHold, TicketingError> {
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(organizer) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if event.cancelled {
+            return Err(TicketingError::EventCancelled);
+        }
+
+        if from_reserved_pool && event.reserved_tickets == 0 {
+            return Err(TicketingError::ReservedPoolExhausted);
+        }
+
+        let seats = match seat {
+            Some(seat) => {
+                let seats = vec![seat];
+                validate_seat_request(event, &seats, 1)?;
+                seats
+            }
+            None => assign_free_seat(event)?,
+        };
+
+        // Comps come out of the first tier with room left
+        let tier_name = event.tiers.iter()
+            .find(|tier| tier.available > 0)
+            .map(|tier| tier.name.clone())
+            .ok_or(TicketingError::InsufficientTickets)?;
+
+        // Once only reserved tickets are left, any comp has to come out of the reserved pool
+        let takes_reserved = from_reserved_pool || event.available_tickets <= event.reserved_tickets;
+
+        event.adjust_inventory(&tier_name, -1)?;
+        if takes_reserved {
+            event.reserved_tickets -= 1;
+        }
+
+        Ok(PurchaseHold {
+            buyer: to,
+            event_id,
+            quantity: 1,
+            requested_quantity: 1,
+            tier_name,
+            seats,
+            total_amount: 0,
+            payment_token: event.payment_token,
+            promo_code: None,
+            is_comp: true,
+            metadata: HashMap::new(),
+        })
+    })
+}
+
+fn complete_comp(hold: PurchaseHold, current_time: u64) -> Result<Ticket, TicketingError> {
+    take_seats(hold.event_id, &hold.seats);
+    adjust_owned_tickets(hold.buyer, hold.event_id, 1);
+
+    let purchase = complete_purchase(hold, current_time);
+
+    TICKETS.with(|tickets| {
+        purchase.ticket_ids.first()
+            .and_then(|ticket_id| tickets.borrow().get(ticket_id).cloned())
+            .ok_or(TicketingError::TicketNotFound)
+    })
+}
+
+// Hands a ticket from the event's reserved pool to `to` free of charge
+#[update]
+async fn issue_comp_ticket(event_id: u64, to: Principal) -> Result<Ticket, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    require_not_paused()?;
+
+    ensure_verification_seed().await;
+
+    let current_time = time();
+    let hold = place_comp_hold(caller, event_id, to, None, true)?;
+    let ticket = complete_comp(hold, current_time)?;
+
+    record_audit(caller, format!("Issued comp ticket {} to {}", ticket.id, to), Some(event_id));
+    Ok(ticket)
+}
+
+// Guest-list pass for press, sponsors and the like: free, from any unsold ticket and
+// outside the recipient's per-user limit
+#[update]
+async fn issue_guest_ticket(event_id: u64, to: Principal, seat: Option<String>) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    require_not_paused()?;
+
+    ensure_verification_seed().await;
+
+    let current_time = time();
+    let hold = place_comp_hold(caller, event_id, to, seat, false)?;
+    let ticket = complete_comp(hold, current_time)?;
+
+    record_audit(caller, format!("Issued guest ticket {} to {}", ticket.id, to), Some(event_id));
+    Ok(ticket.id)
+}
+
+fn redacted_for(caller: Principal, ticket: Ticket) -> Ticket {
+    let event = get_event(ticket.event_id).ok();
+    redacted_by(caller, event.as_ref(), ticket)
+}
+
+// Blanks a ticket's entry code unless `caller` owns it or works the event's door, and its
+// attendee details unless `caller` owns it or administers the event
+fn redacted_by(caller: Principal, event: Option<&Event>, mut ticket: Ticket) -> Ticket {
+    if ticket.owner != caller {
+        if !event.is_some_and(|event| event.can_check_in(caller)) {
+            ticket.verification_code.clear();
+        }
+        if !event.is_some_and(|event| event.is_event_admin(caller)) {
+            ticket.metadata.clear();
+        }
+    }
+    ticket
+}
+
+#[query]
+fn get_user_tickets(user: Principal) -> Vec<Ticket> {
+    let caller = ic_cdk::caller();
+    TICKETS.with(|tickets| {
+        tickets.borrow().values()
+            .filter(|ticket| ticket.owner == user)
+            .map(|ticket| redacted_for(caller, ticket.clone()))
+            .collect()
+    })
+}
+
+#[query]
+fn get_user_tickets_paginated(user: Principal, offset: u64, limit: u64) -> (Vec<Ticket>, u64) {
+    let caller = ic_cdk::caller();
+    let limit = limit.min(MAX_PAGE_SIZE) as usize;
+    TICKETS.with(|tickets| {
+        let tickets = tickets.borrow();
+        let owned = || tickets.values().filter(|ticket| ticket.owner == user);
+        let page = owned()
+            .skip(offset as usize)
+            .take(limit)
+            .map(|ticket| redacted_for(caller, ticket.clone()))
+            .collect();
+        (page, owned().count() as u64)
+    })
+}
+
+// Distinct events the user holds a ticket for, soonest first
+#[query]
+fn get_user_events(user: Principal) -> Vec<Event> {
+    let current_time = time();
+    let event_ids: BTreeSet<u64> = TICKETS.with(|tickets| {
+        tickets.borrow().values()
+            .filter(|ticket| ticket.owner == user)
+            .map(|ticket| ticket.event_id)
+            .collect()
+    });
+
+    let mut events: Vec<Event> = EVENTS.with(|events| {
+        let events = events.borrow();
+        event_ids.iter()
+            .filter_map(|event_id| events.get(event_id).map(|event| refreshed(event, current_time)))
+            .collect()
+    });
+
+    events.sort_by_key(|event| event.date);
+    events
+}
+
+// Only the owner, the organizer and event staff may see a ticket's verification code, and
+// only the owner and event admins its attendee details
+#[query]
+fn get_ticket(ticket_id: u64) -> Result<Ticket, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    if ticket.owner == caller {
+        return Ok(ticket);
+    }
+
+    let event = get_event(ticket.event_id)?;
+    if !event.can_check_in(caller) {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    Ok(redacted_by(caller, Some(&event), ticket))
+}
+
+// Everything needed to render a ticket in one call, with the same access rules as
+// `get_ticket`. Archived events are still returned.
+#[query]
+fn get_ticket_with_event(ticket_id: u64) -> Result<(Ticket, Event), TicketingError> {
+    let ticket = get_ticket(ticket_id)?;
+    let event = get_event(ticket.event_id)?;
+    Ok((ticket, event))
+}
+
+// Only the ticket's owner and the event's organizer (or a FullAdmin co-organizer) may
+// read the attendee details
+#[query]
+fn get_ticket_metadata(ticket_id: u64) -> Result<HashMap<String, String>, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    if ticket.owner != caller && !get_event(ticket.event_id)?.is_event_admin(caller) {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    Ok(ticket.metadata)
+}
+
+// Replaces a lost code with a fresh one; the old code stops verifying immediately
+#[update]
+async fn regenerate_verification_code(ticket_id: u64) -> Result<String, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    ensure_verification_seed().await;
+
+    TICKETS.with(|tickets| {
+        let mut tickets = tickets.borrow_mut();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or(TicketingError::TicketNotFound)?;
+
+        if ticket.owner != caller {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if ticket.is_used {
+            return Err(TicketingError::AlreadyUsed);
+        }
+
+        revoke_verification_code(&ticket.verification_code);
+        ticket.verification_code = issue_verification_code(ticket_id);
+        Ok(ticket.verification_code.clone())
+    })
+}
+
+#[query]
+fn get_user_purchases(user: Principal) -> Vec<Purchase> {
+    PURCHASES.with(|purchases| {
+        purchases.borrow().values()
+            .filter(|purchase| purchase.buyer == user && !purchase.is_free)
+            .cloned()
+            .collect()
+    })
+}
+
+// Whether `hash` matches the purchase's terms as currently stored, or a receipt issued
+// for it before a split; a mismatch means either the receipt or the record was altered
+#[query]
+fn verify_receipt(purchase_id: u64, hash: String) -> bool {
+    let hash = hash.to_lowercase();
+    PURCHASES.with(|purchases| {
+        purchases.borrow().get(&purchase_id).is_some_and(|purchase| {
+            compute_receipt_hash(purchase) == hash || purchase.superseded_receipt_hashes.contains(&hash)
+        })
+    })
+}
+
+// Single purchase for a receipt view; visible to the buyer and the event's organizer
+#[query]
+fn get_purchase(purchase_id: u64) -> Result<Purchase, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let purchase = PURCHASES.with(|purchases| {
+        purchases.borrow().get(&purchase_id)
+            .cloned()
+            .ok_or(TicketingError::PurchaseNotFound)
+    })?;
+
+    if purchase.buyer != caller {
+        let organizer = EVENTS.with(|events| {
+            events.borrow().get(&purchase.event_id).map(|event| event.organizer)
+        });
+        if organizer != Some(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+    }
+
+    Ok(purchase)
+}
+
+// The buyer's tickets from a purchase, codes included, for display right after checkout.
+// Tickets since transferred, resold or refunded are left out.
+#[query]
+fn get_purchase_tickets(purchase_id: u64) -> Result<Vec<Ticket>, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let purchase = PURCHASES.with(|purchases| {
+        purchases.borrow().get(&purchase_id)
+            .cloned()
+            .ok_or(TicketingError::PurchaseNotFound)
+    })?;
+
+    if purchase.buyer != caller {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    Ok(TICKETS.with(|tickets| {
+        let tickets = tickets.borrow();
+        purchase.ticket_ids.iter()
+            .filter_map(|ticket_id| tickets.get(ticket_id))
+            .filter(|ticket| ticket.purchase_id == purchase_id && ticket.owner == caller)
+            .cloned()
+            .collect()
+    }))
+}
+
+// Carves `ticket_ids` out of a purchase into a new purchase record for the same buyer, e.g.
+// before gifting part of an order. The new record covers what its tickets cost and a
+// proportional share of the platform fee, and links back through `split_from`. The
+// original shrinks by the same amounts and is re-hashed, keeping its earlier receipt
+// in `superseded_receipt_hashes` so it still verifies.
+#[update]
+fn split_purchase(purchase_id: u64, ticket_ids: Vec<u64>) -> Result<Purchase, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let original = PURCHASES.with(|purchases| {
+        purchases.borrow().get(&purchase_id)
+            .cloned()
+            .ok_or(TicketingError::PurchaseNotFound)
+    })?;
+
+    if original.buyer != caller {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    // The original has to keep at least one of the tickets still attached to it
+    let unique: BTreeSet<&u64> = ticket_ids.iter().collect();
+    let attached = TICKETS.with(|tickets| {
+        let tickets = tickets.borrow();
+        original.ticket_ids.iter()
+            .filter(|ticket_id| tickets.get(ticket_id).is_some_and(|ticket| ticket.purchase_id == purchase_id))
+            .count()
+    });
+    if ticket_ids.is_empty() || unique.len() != ticket_ids.len() || ticket_ids.len() >= attached {
+        return Err(TicketingError::InvalidQuantity);
+    }
+
+    let carved_amount = TICKETS.with(|tickets| {
+        let tickets = tickets.borrow();
+        ticket_ids.iter().try_fold(0u64, |total, ticket_id| {
+            let ticket = tickets.get(ticket_id)
+                .filter(|ticket| ticket.purchase_id == purchase_id)
+                .ok_or(TicketingError::TicketNotFound)?;
+            if ticket.owner != caller {
+                return Err(TicketingError::Unauthorized);
+            }
+            total.checked_add(ticket.price_paid).ok_or(TicketingError::AmountOverflow)
+        })
+    })?;
+
+    let carved_fee = if original.total_amount == 0 {
+        0
+    } else {
+        (original.fee_amount as u128 * carved_amount as u128 / original.total_amount as u128) as u64
+    };
+
+    let new_purchase_id = PURCHASE_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        *counter += 1;
+        *counter
+    });
+
+    let carved_quantity = ticket_ids.len() as u32;
+    let mut carved = Purchase {
+        id: new_purchase_id,
+        quantity: carved_quantity,
+        requested_quantity: carved_quantity,
+        total_amount: carved_amount,
+        fee_amount: carved_fee,
+        ticket_ids: ticket_ids.clone(),
+        receipt_hash: String::new(),
+        split_from: Some(purchase_id),
+        superseded_receipt_hashes: Vec::new(),
+        ..original.clone()
+    };
+    carved.receipt_hash = compute_receipt_hash(&carved);
+
+    let mut remaining = original;
+    remaining.quantity = remaining.quantity.saturating_sub(carved_quantity);
+    remaining.requested_quantity = remaining.requested_quantity.saturating_sub(carved_quantity);
+    remaining.total_amount = remaining.total_amount.saturating_sub(carved_amount);
+    remaining.fee_amount = remaining.fee_amount.saturating_sub(carved_fee);
+    remaining.ticket_ids.retain(|ticket_id| !unique.contains(&ticket_id));
+    let superseded = std::mem::take(&mut remaining.receipt_hash);
+    remaining.superseded_receipt_hashes.push(superseded);
+    remaining.receipt_hash = compute_receipt_hash(&remaining);
+
+    TICKETS.with(|tickets| {
+        let mut tickets = tickets.borrow_mut();
+        for ticket_id in &ticket_ids {
+            if let Some(ticket) = tickets.get_mut(ticket_id) {
+                ticket.purchase_id = new_purchase_id;
+            }
+        }
+    });
+
+    PURCHASES.with(|purchases| {
+        let mut purchases = purchases.borrow_mut();
+        purchases.insert(purchase_id, remaining);
+        purchases.insert(new_purchase_id, carved.clone());
+    });
+
+    USER_PROFILES.with(|profiles| {
+        if let Some(profile) = profiles.borrow_mut().get_mut(&caller) {
+            profile.purchases.push(new_purchase_id);
+        }
+    });
+
+    Ok(carved)
+}
+
+#[query]
+fn verify_ticket(ticket_id: u64, verification_code: String) -> Result<Ticket, TicketingError> {
+    if !TICKETS.with(|tickets| tickets.borrow().contains_key(&ticket_id)) {
+        return Err(TicketingError::TicketNotFound);
+    }
+
+    let ticket = verify_by_code(verification_code)?;
+    if ticket.id != ticket_id {
+        return Err(TicketingError::InvalidVerificationCode);
+    }
+
+    Ok(ticket)
+}
+
+// Verifies several (ticket id, code) pairs at once; pairs past MAX_BATCH_SIZE are ignored
+#[query]
+fn verify_tickets_batch(codes: Vec<(u64, String)>) -> Vec<Result<Ticket, TicketingError>> {
+    codes.into_iter()
+        .take(MAX_BATCH_SIZE)
+        .map(|(ticket_id, code)| verify_ticket(ticket_id, code))
+        .collect()
+}
+
+// Lets gate staff verify a scanned QR code without knowing the ticket id
+#[query]
+fn verify_by_code(code: String) -> Result<Ticket, TicketingError> {
+    let ticket_id = VERIFICATION_CODES.with(|codes| codes.borrow().get(&code).copied())
+        .ok_or(TicketingError::InvalidVerificationCode)?;
+
+    TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })
+}
+
+// Signs the ticket for an offline-verifiable QR code. Gates check the signature against
+// `get_verification_pubkey` and the owner against the holder's identity; a signature
+// stays valid after the ticket is used, so gates should still sync check-ins when online.
+#[update]
+async fn sign_ticket(ticket_id: u64) -> Result<TicketSignature, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    let event = get_event(ticket.event_id)?;
+
+    if ticket.owner != caller && !event.can_check_in(caller) {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    if ticket.is_used {
+        return Err(TicketingError::AlreadyUsed);
+    }
+
+    if is_listed_for_resale(ticket_id) {
+        return Err(TicketingError::TicketListedForResale);
+    }
+
+    if is_refund_in_progress(ticket_id) {
+        return Err(TicketingError::RefundInProgress);
+    }
+
+    ensure_verification_pubkey().await?;
+
+    let argument = SignWithEcdsaArgument {
+        message_hash: Sha256::digest(ticket_signature_payload(&ticket)).to_vec(),
+        derivation_path: Vec::new(),
+        key_id: ecdsa_key_id(),
+    };
+
+    let (response,) = sign_with_ecdsa(argument).await
+        .map_err(|(code, message)| {
+            ic_cdk::println!("Failed to sign ticket {} ({:?}): {}", ticket_id, code, message);
+            TicketingError::SigningFailed
+        })?;
+
+    Ok(TicketSignature {
+        event_id: ticket.event_id,
+        ticket_id,
+        owner: ticket.owner,
+        signature: response.signature,
+    })
+}
+
+// SEC1-encoded secp256k1 key that ticket signatures verify against; fetched shortly
+// after install or upgrade
+#[query]
+fn get_verification_pubkey() -> Result<Vec<u8>, TicketingError> {
+    VERIFICATION_PUBKEY.with(|key| {
+        let key = key.borrow();
+        if key.is_empty() {
+            Err(TicketingError::SigningFailed)
+        } else {
+            Ok(key.clone())
+        }
+    })
+}
+
+#[update]
+fn use_ticket(
+    ticket_id: u64,
+    verification_code: String,
+    expected_event_id: u64,
+) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+    
+    let notify_canister = TICKETS.with(|tickets| {
+        let mut tickets = tickets.borrow_mut();
+        let ticket = tickets.get_mut(&ticket_id)
+            .ok_or(TicketingError::TicketNotFound)?;
+
+        // Guards against check-ins at the wrong gate of a multi-event venue
+        if ticket.event_id != expected_event_id {
+            return Err(TicketingError::WrongEvent);
+        }
+
+        if ticket.verification_code != verification_code {
+            return Err(TicketingError::InvalidVerificationCode);
+        }
+
+        if ticket.is_used {
+            // A scanner double-firing the same check-in gets the original success back;
+            // nothing is rewarded or notified a second time
+            let rescan = ticket.used_by == Some(caller)
+                && ticket.used_at.is_some_and(|used_at| current_time <= used_at.saturating_add(RESCAN_WINDOW));
+            if rescan {
+                return Ok(None);
+            }
+            return Err(TicketingError::AlreadyUsed);
+        }
+
+        if is_listed_for_resale(ticket_id) {
+            return Err(TicketingError::TicketListedForResale);
+        }
+
+        if is_refund_in_progress(ticket_id) {
+            return Err(TicketingError::RefundInProgress);
+        }
+
+        // Check if caller is authorized (event organizer or event staff)
+        let event = EVENTS.with(|events| {
+            events.borrow().get(&ticket.event_id).cloned()
+        }).ok_or(TicketingError::EventNotFound)?;
+
+        if !event.can_check_in(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if event.cancelled {
+            return Err(TicketingError::EventCancelled);
+        }
+
+        if event.check_in_window
+            .is_some_and(|window| current_time > event.date.saturating_add(window))
+        {
+            return Err(TicketingError::EventEnded);
+        }
+
+        ticket.is_used = true;
+        ticket.used_at = Some(current_time);
+        ticket.used_by = Some(caller);
+        adjust_reputation(ticket.owner, ATTENDANCE_REWARD);
+        Ok(event.notify_canister)
+    })?;
+
+    // Best effort: a failing callback must never block check-in
+    if let Some(canister) = notify_canister {
+        let args = (expected_event_id, ticket_id, current_time);
+        if let Err(code) = ic_cdk::notify(canister, TICKET_USED_CALLBACK, args) {
+            ic_cdk::println!("Failed to notify {} of ticket {} ({:?})", canister, ticket_id, code);
+        }
+    }
+
+    Ok(())
+}
+
+// Attendee ownership data is only visible to the organizer and staff; custom field answers
+// only to event admins
+#[query]
+fn get_event_tickets(event_id: u64) -> Result<Vec<Ticket>, TicketingError> {
+    let caller = ic_cdk::caller();
+    let event = get_event(event_id)?;
+
+    if !event.can_check_in(caller) {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    Ok(TICKETS.with(|tickets| {
+        tickets.borrow().values()
+            .filter(|ticket| ticket.event_id == event_id)
+            .map(|ticket| redacted_by(caller, Some(&event), ticket.clone()))
+            .collect()
+    }))
+}
+
+// Checked-in (`used`) or not-yet-arrived tickets, for reconciling at close
+#[query]
+fn get_event_tickets_by_status(event_id: u64, used: bool) -> Result<Vec<Ticket>, TicketingError> {
+    let caller = ic_cdk::caller();
+    let event = get_event(event_id)?;
+
+    if !event.can_check_in(caller) {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    Ok(TICKETS.with(|tickets| {
+        tickets.borrow().values()
+            .filter(|ticket| ticket.event_id == event_id && ticket.is_used == used)
+            .map(|ticket| redacted_by(caller, Some(&event), ticket.clone()))
+            .collect()
+    }))
+}
+
+// Door list as CSV for the organizer and staff, one row per ticket in ticket id
+// order so repeated exports diff cleanly
+#[query]
+fn export_attendees(event_id: u64) -> Result<String, TicketingError> {
+    let tickets = get_event_tickets(event_id)?;
+
+    let mut csv = String::from("owner,seat_number,is_used,purchase_time\n");
+    for ticket in tickets {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            ticket.owner,
+            csv_field(&ticket.seat_number),
+            ticket.is_used,
+            ticket.purchase_time,
+        ));
+    }
+
+    Ok(csv)
+}
+
+#[query]
+fn get_event_checkins(event_id: u64) -> Result<Vec<Ticket>, TicketingError> {
+    get_event_tickets_by_status(event_id, true)
+}
+
+#[update]
+fn transfer_ticket(ticket_id: u64, to: Principal) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    require_not_paused()?;
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    if ticket.owner != caller {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    if ticket.is_used {
+        return Err(TicketingError::AlreadyUsed);
+    }
+
+    if is_listed_for_resale(ticket_id) {
+        return Err(TicketingError::TicketListedForResale);
+    }
+
+    if is_refund_in_progress(ticket_id) {
+        return Err(TicketingError::RefundInProgress);
+    }
+
+    require_transferable(ticket.event_id)?;
+
+    reassign_ticket(ticket_id, to)
+}
+
+// Offers the ticket to `to`, who has to accept it before ownership moves
+#[update]
+fn offer_ticket(ticket_id: u64, to: Principal) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    require_not_paused()?;
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    if ticket.owner != caller || to == caller {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    if ticket.is_used {
+        return Err(TicketingError::AlreadyUsed);
+    }
+
+    if is_listed_for_resale(ticket_id) {
+        return Err(TicketingError::TicketListedForResale);
+    }
+
+    if is_refund_in_progress(ticket_id) {
+        return Err(TicketingError::RefundInProgress);
+    }
+
+    require_transferable(ticket.event_id)?;
+
+    TICKET_OFFERS.with(|offers| {
+        let mut offers = offers.borrow_mut();
+        if offers.get(&ticket_id).is_some_and(|offer| current_time <= offer.expires_at) {
+            return Err(TicketingError::OfferAlreadyPending);
+        }
+
+        offers.insert(ticket_id, TicketOffer {
+            ticket_id,
+            event_id: ticket.event_id,
+            from: caller,
+            to,
+            offered_at: current_time,
+            expires_at: current_time + TICKET_OFFER_TTL,
+        });
+        Ok(())
+    })
+}
+
+// Completes an offer made to the caller, provided the sender still holds the ticket
+#[update]
+fn accept_ticket_offer(ticket_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    require_not_paused()?;
+
+    let offer = TICKET_OFFERS.with(|offers| {
+        offers.borrow().get(&ticket_id)
+            .filter(|offer| offer.to == caller)
+            .cloned()
+            .ok_or(TicketingError::OfferNotFound)
+    })?;
+
+    if current_time > offer.expires_at {
+        TICKET_OFFERS.with(|offers| offers.borrow_mut().remove(&ticket_id));
+        return Err(TicketingError::OfferExpired);
+    }
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    // The sender may have used, sold or given the ticket away since making the offer
+    if ticket.owner != offer.from {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    if ticket.is_used {
+        return Err(TicketingError::AlreadyUsed);
+    }
+
+    if is_listed_for_resale(ticket_id) {
+        return Err(TicketingError::TicketListedForResale);
+    }
+
+    if is_refund_in_progress(ticket_id) {
+        return Err(TicketingError::RefundInProgress);
+    }
+
+    require_transferable(ticket.event_id)?;
+
+    TICKET_OFFERS.with(|offers| offers.borrow_mut().remove(&ticket_id));
+    reassign_ticket(ticket_id, caller)
+}
+
+// Turns down an offer; the sender can also use this to withdraw one
+#[update]
+fn decline_ticket_offer(ticket_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    TICKET_OFFERS.with(|offers| {
+        let mut offers = offers.borrow_mut();
+        let is_party = offers.get(&ticket_id)
+            .is_some_and(|offer| offer.to == caller || offer.from == caller);
+        if !is_party {
+            return Err(TicketingError::OfferNotFound);
+        }
+
+        offers.remove(&ticket_id);
+        Ok(())
+    })
+}
+
+// Offers waiting on `user`, oldest first
+#[query]
+fn get_pending_offers(user: Principal) -> Vec<TicketOffer> {
+    let current_time = time();
+
+    let mut offers: Vec<TicketOffer> = TICKET_OFFERS.with(|offers| {
+        offers.borrow().values()
+            .filter(|offer| offer.to == user && current_time <= offer.expires_at)
+            .cloned()
+            .collect()
+    });
+
+    offers.sort_by_key(|offer| offer.offered_at);
+    offers
+}
+
+// Moves an attendee to another free seat, e.g. for a broken seat or an upgrade
+#[update]
+fn reassign_seat(ticket_id: u64, new_seat: String) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    let event = get_event(ticket.event_id)?;
+
+    if !event.is_event_admin(caller) {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    let is_taken = TAKEN_SEATS.with(|taken| {
+        taken.borrow().get(&event.id).is_some_and(|taken| taken.contains(&new_seat))
+    });
+    if !event.seat_map.contains(&new_seat) || is_taken {
+        return Err(TicketingError::SeatUnavailable);
+    }
+
+    release_seats(event.id, std::slice::from_ref(&ticket.seat_number));
+    take_seats(event.id, std::slice::from_ref(&new_seat));
+
+    // Swaps after entry are allowed but worth a trail
+    if ticket.is_used {
+        ic_cdk::println!(
+            "Moved used ticket {} from seat {} to {}",
+            ticket_id, ticket.seat_number, new_seat
+        );
+    }
+
+    TICKETS.with(|tickets| {
+        if let Some(ticket) = tickets.borrow_mut().get_mut(&ticket_id) {
+            ticket.seat_number = new_seat;
+        }
+    });
+
+    Ok(())
+}
+
+#[update]
+async fn refund_ticket(ticket_id: u64) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    if ticket.owner != caller {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    if ticket.is_used {
+        return Err(TicketingError::AlreadyUsed);
+    }
+
+    // A comp may have come out of the reserved pool, and refunding it would release it to
+    // public sale
+    if ticket.is_comp {
+        return Err(TicketingError::CompNotRefundable);
+    }
+
+    if is_listed_for_resale(ticket_id) {
+        return Err(TicketingError::TicketListedForResale);
+    }
+
+    if is_refund_in_progress(ticket_id) {
+        return Err(TicketingError::RefundInProgress);
+    }
+
+    let event = get_event(ticket.event_id)?;
+
+    // Holders of a cancelled event can always get their money back, and holders who
+    // haven't acknowledged a reschedule can until the grace period runs out
+    let reschedule_refund = ticket.needs_reacknowledgement && event.rescheduled_at
+        .is_some_and(|rescheduled_at| current_time <= rescheduled_at + RESCHEDULE_GRACE_PERIOD);
+    let penalty_exempt = event.cancelled || reschedule_refund;
+    if event.refunds_require_approval && !penalty_exempt {
+        return Err(TicketingError::RefundApprovalRequired);
+    }
+
+    let window_closed = current_time > event.refund_deadline || current_time >= event.date;
+    if window_closed && !penalty_exempt {
+        return Err(TicketingError::RefundWindowClosed);
+    }
+
+    let reason = if event.cancelled {
+        RefundReason::EventCancelled
+    } else if reschedule_refund {
+        RefundReason::Reschedule
+    } else {
+        RefundReason::UserRequested
+    };
+    let refund_amount = pay_out_refund(&event, &ticket, reason).await?;
+
+    if !penalty_exempt && event.date.saturating_sub(current_time) < LATE_REFUND_WINDOW {
+        adjust_reputation(caller, LATE_REFUND_PENALTY);
+    }
+
+    Ok(refund_amount)
+}
+
+// Asks the organizer for a refund, which they can grant outside the self-service
+// refund window or for events that require approval
+#[update]
+fn request_refund(ticket_id: u64, reason: String) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    if reason.len() > MAX_REFUND_REASON_LENGTH {
+        return Err(TicketingError::ReasonTooLong);
+    }
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    if ticket.owner != caller {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    if ticket.is_used {
+        return Err(TicketingError::AlreadyUsed);
+    }
+
+    // See `refund_ticket`
+    if ticket.is_comp {
+        return Err(TicketingError::CompNotRefundable);
+    }
+
+    if is_listed_for_resale(ticket_id) {
+        return Err(TicketingError::TicketListedForResale);
+    }
+
+    if is_refund_in_progress(ticket_id) {
+        return Err(TicketingError::RefundInProgress);
+    }
+
+    let event = get_event(ticket.event_id)?;
+
+    if current_time >= event.date && !event.cancelled {
+        return Err(TicketingError::EventAlreadyHappened);
+    }
+
+    let already_requested = REFUND_REQUESTS.with(|requests| {
+        requests.borrow().values().any(|request| {
+            request.ticket_id == ticket_id && request.status == RefundRequestStatus::Pending
+        })
+    });
+    if already_requested {
+        return Err(TicketingError::RefundAlreadyRequested);
+    }
+
+    let request_id = REFUND_REQUEST_COUNTER.with(|counter| {
+        let mut counter = counter.borrow_mut();
+        *counter += 1;
+        *counter
+    });
+
+    let request = RefundRequest {
+        id: request_id,
+        ticket_id,
+        event_id: ticket.event_id,
+        requester: caller,
+        reason,
+        requested_at: current_time,
+        status: RefundRequestStatus::Pending,
+        resolved_at: None,
+    };
+
+    REFUND_REQUESTS.with(|requests| {
+        requests.borrow_mut().insert(request_id, request);
+    });
+
+    Ok(request_id)
+}
+
+// Looks up a pending refund request on one of the caller's events
+fn get_pending_refund_request(request_id: u64, organizer: Principal) -> Result<(RefundRequest, Event), TicketingError> {
+    let request = REFUND_REQUESTS.with(|requests| {
+        requests.borrow().get(&request_id)
+            .cloned()
+            .ok_or(TicketingError::RefundRequestNotFound)
+    })?;
+
+    let event = get_event(request.event_id)?;
+
+    if !event.can_manage_finances(organizer) {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    if request.status != RefundRequestStatus::Pending {
+        return Err(TicketingError::RefundRequestResolved);
+    }
+
+    Ok((request, event))
+}
+
+fn set_refund_request_status(request_id: u64, status: RefundRequestStatus, resolved_at: Option<u64>) {
+    REFUND_REQUESTS.with(|requests| {
+        if let Some(request) = requests.borrow_mut().get_mut(&request_id) {
+            request.status = status;
+            request.resolved_at = resolved_at;
+        }
+    });
+}
+
+// Grants a refund request: the ticket goes back on sale and the requester is paid
+// as for a self-service refund, without the late refund penalty
+#[update]
+async fn approve_refund(request_id: u64) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    let (request, event) = get_pending_refund_request(request_id, caller)?;
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&request.ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    // The ticket may have changed hands or been scanned since the request
+    if ticket.owner != request.requester {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    if ticket.is_used {
+        return Err(TicketingError::AlreadyUsed);
+    }
+
+    if is_listed_for_resale(ticket.id) {
+        return Err(TicketingError::TicketListedForResale);
+    }
+
+    if is_refund_in_progress(ticket.id) {
+        return Err(TicketingError::RefundInProgress);
+    }
+
+    // Resolve the request before the ledger call so it can't be approved twice
+    set_refund_request_status(request_id, RefundRequestStatus::Approved, Some(current_time));
+
+    match pay_out_refund(&event, &ticket, RefundReason::UserRequested).await {
+        Ok(refund_amount) => Ok(refund_amount),
+        Err(err) => {
+            set_refund_request_status(request_id, RefundRequestStatus::Pending, None);
+            Err(err)
+        }
+    }
+}
+
+// Turns a refund request down; the requester keeps the ticket
+#[update]
+fn deny_refund(request_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    get_pending_refund_request(request_id, caller)?;
+    set_refund_request_status(request_id, RefundRequestStatus::Denied, Some(time()));
+
+    Ok(())
+}
+
+// Refund requests awaiting a decision, oldest first; organizer only
+#[query]
+fn get_pending_refunds(event_id: u64) -> Result<Vec<RefundRequest>, TicketingError> {
+    let caller = ic_cdk::caller();
+    let event = get_event(event_id)?;
+
+    if !event.can_manage_finances(caller) {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    Ok(REFUND_REQUESTS.with(|requests| {
+        requests.borrow().values()
+            .filter(|request| {
+                request.event_id == event_id && request.status == RefundRequestStatus::Pending
+            })
+            .cloned()
+            .collect()
+    }))
+}
+
+// Every refund paid out for the event, oldest first
+#[query]
+fn get_refunds_for_event(event_id: u64) -> Result<Vec<RefundRecord>, TicketingError> {
+    let caller = ic_cdk::caller();
+    let event = get_event(event_id)?;
+
+    if !event.can_manage_finances(caller) {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    Ok(REFUNDS.with(|refunds| {
+        refunds.borrow().iter()
+            .filter(|refund| refund.event_id == event_id)
+            .cloned()
+            .collect()
+    }))
+}
+
+#[query]
+fn get_user_refunds(user: Principal) -> Vec<RefundRecord> {
+    REFUNDS.with(|refunds| {
+        refunds.borrow().iter()
+            .filter(|refund| refund.recipient == user)
+            .cloned()
+            .collect()
+    })
+}
+
+#[update]
+fn list_ticket_for_resale(ticket_id: u64, price_icp: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    require_not_paused()?;
+
+    let ticket = TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })?;
+
+    if ticket.owner != caller {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    if ticket.is_used {
+        return Err(TicketingError::AlreadyUsed);
+    }
+
+    if RESALES_IN_PROGRESS.with(|in_progress| in_progress.borrow().contains(&ticket_id)) {
+        return Err(TicketingError::TicketListedForResale);
+    }
+
+    if is_refund_in_progress(ticket_id) {
+        return Err(TicketingError::RefundInProgress);
+    }
+
+    let event = get_event(ticket.event_id)?;
+
+    if event.cancelled {
+        return Err(TicketingError::EventCancelled);
+    }
+
+    if current_time >= event.date {
+        return Err(TicketingError::EventAlreadyHappened);
+    }
+
+    if !event.transferable {
+        return Err(TicketingError::NotTransferable);
+    }
+
+    // Resale is capped at what the ticket cost to keep scalpers out
+    if price_icp > event.resale_cap(&ticket) {
+        return Err(TicketingError::ResalePriceTooHigh);
+    }
+
+    let listing = ResaleListing {
+        ticket_id,
+        event_id: ticket.event_id,
+        seller: caller,
+        price_icp,
+        listed_at: current_time,
+    };
+
+    RESALE_LISTINGS.with(|listings| {
+        listings.borrow_mut().insert(ticket_id, listing);
+    });
+
+    Ok(())
+}
+
+#[update]
+fn cancel_resale_listing(ticket_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    RESALE_LISTINGS.with(|listings| {
+        let mut listings = listings.borrow_mut();
+        let listing = listings.get(&ticket_id)
+            .ok_or(TicketingError::NotListedForResale)?;
+
+        if listing.seller != caller {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        listings.remove(&ticket_id);
+        Ok(())
+    })
+}
+
+#[update]
+async fn buy_resale_ticket(ticket_id: u64) -> Result<Ticket, TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    require_not_paused()?;
+
+    let listing = RESALE_LISTINGS.with(|listings| {
+        listings.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::NotListedForResale)
+    })?;
+
+    if listing.seller == caller {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    let event = get_event(listing.event_id)?;
+
+    if current_time >= event.date {
+        return Err(TicketingError::EventAlreadyHappened);
+    }
+
+    if !event.transferable {
+        return Err(TicketingError::NotTransferable);
+    }
+
+    // Lock the ticket while the buyer pays the seller
+    RESALE_LISTINGS.with(|listings| listings.borrow_mut().remove(&ticket_id));
+    RESALES_IN_PROGRESS.with(|in_progress| in_progress.borrow_mut().insert(ticket_id));
+
+    let payment = collect_payment(event.payment_token, caller, listing.seller, listing.price_icp).await;
+
+    RESALES_IN_PROGRESS.with(|in_progress| in_progress.borrow_mut().remove(&ticket_id));
+
+    if let Err(err) = payment {
+        RESALE_LISTINGS.with(|listings| {
+            listings.borrow_mut().insert(ticket_id, listing);
+        });
+        return Err(err);
+    }
+
+    reassign_ticket(ticket_id, caller)?;
+
+    TICKETS.with(|tickets| {
+        tickets.borrow().get(&ticket_id)
+            .cloned()
+            .ok_or(TicketingError::TicketNotFound)
+    })
+}
+
+#[query]
+fn get_resale_listings(event_id: u64) -> Vec<ResaleListing> {
+    RESALE_LISTINGS.with(|listings| {
+        listings.borrow().values()
+            .filter(|listing| listing.event_id == event_id)
+            .cloned()
+            .collect()
+    })
+}
+
+#[update]
+fn join_waitlist(event_id: u64) -> Result<u32, TicketingError> {
+    let caller = ic_cdk::caller();
+    let event = get_event(event_id)?;
+
+    // Only sold-out events have a waitlist; comp-only reserved tickets don't count
+    if event.available_tickets > event.reserved_tickets {
+        return Err(TicketingError::TicketsStillAvailable);
+    }
+
+    WAITLISTS.with(|waitlists| {
+        let mut waitlists = waitlists.borrow_mut();
+        let waitlist = waitlists.entry(event_id).or_default();
+
+        if waitlist.contains(&caller) {
+            return Err(TicketingError::AlreadyOnWaitlist);
+        }
+
+        waitlist.push(caller);
+        Ok(waitlist.len() as u32)
+    })
+}
+
+#[update]
+fn leave_waitlist(event_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    WAITLISTS.with(|waitlists| {
+        let mut waitlists = waitlists.borrow_mut();
+        let waitlist = waitlists.get_mut(&event_id)
+            .filter(|waitlist| waitlist.contains(&caller))
+            .ok_or(TicketingError::NotOnWaitlist)?;
+
+        waitlist.retain(|principal| *principal != caller);
+        Ok(())
+    })
+}
+
+// Positions are 1-based
+#[query]
+fn get_waitlist_position(event_id: u64) -> Result<u32, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    WAITLISTS.with(|waitlists| {
+        waitlists.borrow().get(&event_id)
+            .and_then(|waitlist| waitlist.iter().position(|principal| *principal == caller))
+            .map(|index| index as u32 + 1)
+            .ok_or(TicketingError::NotOnWaitlist)
+    })
+}
+
+#[update]
+fn follow_event(event_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    get_event(event_id)?;
+
+    let mut profile = get_or_create_user_profile(caller);
+    if profile.followed_events.contains(&event_id) {
+        return Err(TicketingError::AlreadyFollowing);
+    }
+    profile.followed_events.push(event_id);
+
+    USER_PROFILES.with(|profiles| {
+        profiles.borrow_mut().insert(caller, profile);
+    });
+
+    Ok(())
+}
+
+#[update]
+fn unfollow_event(event_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    USER_PROFILES.with(|profiles| {
+        let mut profiles = profiles.borrow_mut();
+        let profile = profiles.get_mut(&caller)
+            .filter(|profile| profile.followed_events.contains(&event_id))
+            .ok_or(TicketingError::NotFollowing)?;
+
+        profile.followed_events.retain(|id| *id != event_id);
+        Ok(())
+    })
+}
+
+// Events the user follows that still exist, soonest first
+#[query]
+fn get_followed_events(user: Principal) -> Vec<Event> {
+    let current_time = time();
+    let event_ids = USER_PROFILES.with(|profiles| {
+        profiles.borrow().get(&user)
+            .map(|profile| profile.followed_events.clone())
+            .unwrap_or_default()
+    });
+
+    let mut events: Vec<Event> = EVENTS.with(|events| {
+        let events = events.borrow();
+        event_ids.iter()
+            .filter_map(|event_id| events.get(event_id).map(|event| refreshed(event, current_time)))
+            .collect()
+    });
+
+    events.sort_by_key(|event| event.date);
+    events
+}
+
+// Open to anyone who attended (had a ticket scanned), once per event, after it took place
+#[update]
+fn submit_review(event_id: u64, rating: u8, comment: String) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    let event = get_event(event_id)?;
+
+    if current_time < event.date {
+        return Err(TicketingError::EventNotFinished);
+    }
+
+    if !(1..=5).contains(&rating) {
+        return Err(TicketingError::InvalidRating);
+    }
+
+    if comment.len() > MAX_REVIEW_LENGTH {
+        return Err(TicketingError::ReviewTooLong);
+    }
+
+    let attended = TICKETS.with(|tickets| {
+        tickets.borrow().values()
+            .any(|ticket| ticket.event_id == event_id && ticket.owner == caller && ticket.is_used)
     });
+    if !attended {
+        return Err(TicketingError::NotAttended);
+    }
 
-    let total_amount = event.price_icp * quantity as u64;
-    let mut ticket_ids = Vec::new();
+    let (review_count, rating_total) = REVIEWS.with(|reviews| {
+        let mut reviews = reviews.borrow_mut();
+        let event_reviews = reviews.entry(event_id).or_default();
 
-    // Create tickets
-    for i in 0..quantity {
-        let ticket_id = TICKET_COUNTER.with(|counter| {
-            let mut counter = counter.borrow_mut();
-            *counter += 1;
-            *counter
+        if event_reviews.iter().any(|review| review.reviewer == caller) {
+            return Err(TicketingError::AlreadyReviewed);
+        }
+
+        event_reviews.push(Review {
+            event_id,
+            reviewer: caller,
+            rating,
+            comment,
+            submitted_at: current_time,
         });
 
-        let seat_number = format!("SEAT-{}-{}", event_id, ticket_id);
-        let verification_code = generate_verification_code(ticket_id, event_id);
+        let rating_total: u64 = event_reviews.iter().map(|review| review.rating as u64).sum();
+        Ok((event_reviews.len() as u32, rating_total))
+    })?;
 
-        let ticket = Ticket {
-            id: ticket_id,
-            event_id,
-            owner: caller,
-            seat_number,
-            purchase_time: current_time,
-            is_used: false,
-            verification_code,
+    EVENTS.with(|events| {
+        if let Some(event) = events.borrow_mut().get_mut(&event_id) {
+            event.review_count = review_count;
+            event.average_rating = Some(rating_total as f64 / review_count as f64);
+        }
+    });
+
+    Ok(())
+}
+
+#[query]
+fn get_event_reviews(event_id: u64) -> Vec<Review> {
+    REVIEWS.with(|reviews| {
+        reviews.borrow().get(&event_id).cloned().unwrap_or_default()
+    })
+}
+
+#[query]
+fn get_event_statistics(
+    event_id: u64,
+) -> Result<(u32, u32, u64, Vec<TierStatistics>, PaymentToken), TicketingError> {
+    let event = get_event(event_id)?;
+
+    // Revenue is what buyers actually paid, after schedules and promo codes. Comps
+    // count as sold but paid nothing, and refunded tickets are gone.
+    let mut revenue: HashMap<String, u64> = HashMap::new();
+    TICKETS.with(|tickets| {
+        for ticket in tickets.borrow().values().filter(|ticket| ticket.event_id == event_id) {
+            *revenue.entry(ticket.tier.clone()).or_insert(0) += ticket.price_paid;
+        }
+    });
+
+    let tier_statistics: Vec<TierStatistics> = event.tiers.iter()
+        .map(|tier| TierStatistics {
+            name: tier.name.clone(),
+            sold: tier.total - tier.available,
+            available: tier.available,
+            revenue: revenue.get(&tier.name).copied().unwrap_or(0),
+        })
+        .collect();
+
+    let sold_tickets = event.total_tickets - event.available_tickets;
+    let total_revenue = tier_statistics.iter().map(|tier| tier.revenue).sum();
+
+    // Revenue is in the event's payment token
+    Ok((sold_tickets, event.available_tickets, total_revenue, tier_statistics, event.payment_token))
+}
+
+// Lifetime ticket revenue across the organizer's events priced in `token` (ICP by
+// default), counted like `get_event_statistics` from what each ticket was bought for
+#[query]
+fn get_organizer_revenue(organizer: Principal, token: Option<PaymentToken>) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+    let token = token.unwrap_or_default();
+
+    if caller != organizer {
+        require_admin(caller)?;
+    }
+
+    let event_ids = ORGANIZER_EVENTS.with(|index| {
+        index.borrow().get(&organizer).cloned().unwrap_or_default()
+    });
+
+    event_ids.into_iter()
+        .filter_map(|event_id| get_event_statistics(event_id).ok())
+        .filter(|(.., payment_token)| *payment_token == token)
+        .try_fold(0u64, |total, (_, _, revenue, ..)| {
+            total.checked_add(revenue).ok_or(TicketingError::AmountOverflow)
+        })
+}
+
+// Tickets sold per `bucket_seconds` interval as (bucket start, tickets) pairs in time
+// order. Bucket starts are timestamps in nanoseconds like `purchase_time`.
+#[query]
+fn get_sales_timeline(event_id: u64, bucket_seconds: u64) -> Result<Vec<(u64, u32)>, TicketingError> {
+    get_event(event_id)?;
+
+    let bucket = bucket_seconds.checked_mul(1_000_000_000)
+        .filter(|bucket| *bucket > 0)
+        .ok_or(TicketingError::InvalidTimeWindow)?;
+
+    let mut timeline: BTreeMap<u64, u32> = BTreeMap::new();
+    PURCHASES.with(|purchases| {
+        let purchases = purchases.borrow();
+        let sales = purchases.values()
+            .filter(|purchase| purchase.event_id == event_id && !purchase.is_free);
+        for purchase in sales {
+            let bucket_start = purchase.purchase_time - purchase.purchase_time % bucket;
+            *timeline.entry(bucket_start).or_insert(0) += purchase.quantity;
+        }
+    });
+
+    Ok(timeline.into_iter().collect())
+}
+
+// Returns (used, unused, attendance percentage) over the tickets sold for the event
+#[query]
+fn get_event_attendance(event_id: u64) -> Result<(u32, u32, f64), TicketingError> {
+    get_event(event_id)?;
+
+    let (used, unused) = TICKETS.with(|tickets| {
+        tickets.borrow().values()
+            .filter(|ticket| ticket.event_id == event_id)
+            .fold((0u32, 0u32), |(used, unused), ticket| {
+                if ticket.is_used {
+                    (used + 1, unused)
+                } else {
+                    (used, unused + 1)
+                }
+            })
+    });
+
+    let sold = used + unused;
+    let attendance_rate = if sold == 0 {
+        0.0
+    } else {
+        used as f64 * 100.0 / sold as f64
+    };
+
+    Ok((used, unused, attendance_rate))
+}
+
+#[update]
+fn deactivate_event(event_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.is_active = false;
+        record_audit(caller, "Deactivated event", Some(event_id));
+        Ok(())
+    })
+}
+
+// Pauses or resumes several of the caller's events at once; ids past MAX_BATCH_SIZE
+// are ignored. A cancelled event can't be reactivated.
+#[update]
+fn set_events_active(event_ids: Vec<u64>, active: bool) -> Vec<Result<(), TicketingError>> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        event_ids.into_iter()
+            .take(MAX_BATCH_SIZE)
+            .map(|event_id| {
+                let event = events.get_mut(&event_id)
+                    .ok_or(TicketingError::EventNotFound)?;
+
+                if !event.is_event_admin(caller) {
+                    return Err(TicketingError::Unauthorized);
+                }
+
+                if active && event.cancelled {
+                    return Err(TicketingError::EventCancelled);
+                }
+
+                event.is_active = active;
+                record_audit(caller, if active { "Reactivated event" } else { "Deactivated event" }, Some(event_id));
+                Ok(())
+            })
+            .collect()
+    })
+}
+
+// Moves a finished (or cancelled) event out of the listing queries. It stays
+// reachable through `get_event` and its tickets remain verifiable.
+#[update]
+fn archive_event(event_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+    let current_time = time();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if current_time < event.date && !event.cancelled {
+            return Err(TicketingError::EventNotFinished);
+        }
+
+        if !event.archived {
+            unindex_event_name(event_id, &event.name);
+        }
+        event.archived = true;
+        event.is_active = false;
+        record_audit(caller, "Archived event", Some(event_id));
+        Ok(())
+    })
+}
+
+// Hands the event, with its staff and everything attached to it, to a new organizer
+#[update]
+fn transfer_event_ownership(event_id: u64, new_organizer: Principal) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let event = get_event(event_id)?;
+    if event.organizer != caller {
+        return Err(TicketingError::Unauthorized);
+    }
+
+    require_authenticated(new_organizer)?;
+
+    // The proceeds still backing the event's tickets move with it, so the new
+    // organizer can cover their refunds
+    let proceeds = event_refund_liability(&event);
+    debit_organizer(caller, event.payment_token, proceeds)?;
+    credit_organizer(new_organizer, event.payment_token, proceeds);
+
+    EVENTS.with(|events| {
+        if let Some(event) = events.borrow_mut().get_mut(&event_id) {
+            event.organizer = new_organizer;
+        }
+    });
+
+    ORGANIZER_EVENTS.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(event_ids) = index.get_mut(&caller) {
+            event_ids.retain(|id| *id != event_id);
+        }
+        index.entry(new_organizer).or_default().push(event_id);
+    });
+
+    record_audit(caller, format!("Transferred event to {}", new_organizer), Some(event_id));
+    Ok(())
+}
+
+#[update]
+fn add_event_staff(event_id: u64, staff: Principal) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.can_manage_staff(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        if !event.staff.contains(&staff) {
+            event.staff.push(staff);
+        }
+        record_audit(caller, format!("Added staff {}", staff), Some(event_id));
+        Ok(())
+    })
+}
+
+#[update]
+fn remove_event_staff(event_id: u64, staff: Principal) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.can_manage_staff(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.staff.retain(|member| *member != staff);
+        record_audit(caller, format!("Removed staff {}", staff), Some(event_id));
+        Ok(())
+    })
+}
+
+// Gives `co_organizer` a share of the organizer's controls, or changes their role if
+// they already have one. Only the organizer and FullAdmin co-organizers may do this.
+#[update]
+fn add_co_organizer(event_id: u64, co_organizer: Principal, role: OrganizerRole) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        match event.co_organizers.iter_mut().find(|(member, _)| *member == co_organizer) {
+            Some((_, current)) => *current = role,
+            None => event.co_organizers.push((co_organizer, role)),
+        }
+        record_audit(caller, format!("Made {} a {:?} co-organizer", co_organizer, role), Some(event_id));
+        Ok(())
+    })
+}
+
+#[update]
+fn remove_co_organizer(event_id: u64, co_organizer: Principal) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        event.co_organizers.retain(|(member, _)| *member != co_organizer);
+        record_audit(caller, format!("Removed co-organizer {}", co_organizer), Some(event_id));
+        Ok(())
+    })
+}
+
+// Cancels the event and refunds every holder what they paid. Returns the number of
+// refunds issued; tickets whose payout fails stay in place so the holder can still
+// claim the money through `refund_ticket`.
+#[update]
+async fn cancel_event(event_id: u64) -> Result<u32, TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let event = EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        if !event.is_event_admin(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        // Flipping the flag up front guards against a second round of refunds
+        if event.cancelled {
+            return Err(TicketingError::EventCancelled);
+        }
+
+        event.cancelled = true;
+        event.is_active = false;
+        Ok(event.clone())
+    })?;
+
+    record_audit(caller, "Cancelled event", Some(event_id));
+
+    RESALE_LISTINGS.with(|listings| {
+        listings.borrow_mut().retain(|_, listing| listing.event_id != event_id);
+    });
+
+    WAITLISTS.with(|waitlists| {
+        waitlists.borrow_mut().remove(&event_id);
+    });
+
+    // Outstanding reservations can no longer be confirmed
+    let reservations: Vec<Reservation> = RESERVATIONS.with(|reservations| {
+        let mut reservations = reservations.borrow_mut();
+        let reservation_ids: Vec<u64> = reservations.values()
+            .filter(|reservation| reservation.hold.event_id == event_id)
+            .map(|reservation| reservation.id)
+            .collect();
+
+        reservation_ids.iter()
+            .filter_map(|id| reservations.remove(id))
+            .collect()
+    });
+
+    for reservation in &reservations {
+        release_hold(&reservation.hold);
+    }
+
+    let ticket_ids: Vec<u64> = TICKETS.with(|tickets| {
+        tickets.borrow().values()
+            .filter(|ticket| ticket.event_id == event_id)
+            .map(|ticket| ticket.id)
+            .collect()
+    });
+
+    let mut refunds_issued = 0;
+    for ticket_id in ticket_ids {
+        // Leave tickets mid-resale or mid-refund alone; the new owner can refund once it settles
+        if is_listed_for_resale(ticket_id) || is_refund_in_progress(ticket_id) {
+            continue;
+        }
+
+        let Some(ticket) = TICKETS.with(|tickets| tickets.borrow().get(&ticket_id).cloned()) else {
+            continue;
         };
 
-        TICKETS.with(|tickets| {
-            tickets.borrow_mut().insert(ticket_id, ticket);
-        });
+        // Tickets whose payout fails, e.g. because the organizer's balance can't cover
+        // it, stay put so they can be refunded later
+        if pay_out_refund(&event, &ticket, RefundReason::EventCancelled).await.is_ok() {
+            refunds_issued += 1;
+        }
+    }
 
-        ticket_ids.push(ticket_id);
+    Ok(refunds_issued)
+}
+
+#[update]
+fn flag_chargeback(purchase_id: u64) -> Result<(), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let buyer = PURCHASES.with(|purchases| {
+        let mut purchases = purchases.borrow_mut();
+        let purchase = purchases.get_mut(&purchase_id)
+            .ok_or(TicketingError::PurchaseNotFound)?;
+
+        let event = get_event(purchase.event_id)?;
+        if !event.can_manage_finances(caller) {
+            return Err(TicketingError::Unauthorized);
+        }
+
+        // Only penalize once per purchase
+        if purchase.chargeback_flagged {
+            return Ok(None);
+        }
+
+        purchase.chargeback_flagged = true;
+        Ok(Some(purchase.buyer))
+    })?;
+
+    if let Some(buyer) = buyer {
+        adjust_reputation(buyer, CHARGEBACK_PENALTY);
     }
 
-    let purchase = Purchase {
-        id: purchase_id,
-        event_id,
-        buyer: caller,
-        quantity,
-        total_amount,
-        purchase_time: current_time,
-        ticket_ids: ticket_ids.clone(),
-    };
+    Ok(())
+}
 
-    // Update state
-    PURCHASES.with(|purchases| {
-        purchases.borrow_mut().insert(purchase_id, purchase.clone());
+#[query]
+fn get_top_attendees(limit: u64) -> Vec<UserProfile> {
+    let mut profiles: Vec<UserProfile> = USER_PROFILES.with(|profiles| {
+        profiles.borrow().values().cloned().collect()
     });
 
-    EVENTS.with(|events| {
-        let mut events = events.borrow_mut();
-        if let Some(event) = events.get_mut(&event_id) {
-            event.available_tickets -= quantity;
-        }
-    });
+    profiles.sort_by_key(|profile| std::cmp::Reverse(profile.reputation_score));
+    profiles.truncate(limit.min(MAX_PAGE_SIZE) as usize);
+    profiles
+}
 
-    USER_EVENT_PURCHASES.with(|purchases| {
-        let mut purchases = purchases.borrow_mut();
-        purchases.insert((caller, event_id), current_user_purchases + quantity);
-    });
+#[update]
+fn set_user_verified(user: Principal, verified: bool) -> Result<(), TicketingError> {
+    require_admin(ic_cdk::caller())?;
+
+    let mut profile = get_or_create_user_profile(user);
+    profile.is_verified = verified;
 
-    // Update user profile
-    let mut profile = get_or_create_user_profile(caller);
-    profile.purchases.push(purchase_id);
-    profile.tickets.extend(ticket_ids);
-    
     USER_PROFILES.with(|profiles| {
-        profiles.borrow_mut().insert(caller, profile);
+        profiles.borrow_mut().insert(user, profile);
     });
 
-    Ok(purchase)
+    record_audit(ic_cdk::caller(), format!("Set verified = {} for {}", verified, user), None);
+    Ok(())
 }
 
 #[query]
-fn get_user_tickets(user: Principal) -> Vec<Ticket> {
-    TICKETS.with(|tickets| {
-        tickets.borrow().values()
-            .filter(|ticket| ticket.owner == user)
-            .cloned()
-            .collect()
-    })
+fn get_admin() -> Option<Principal> {
+    ADMIN.with(|admin| *admin.borrow())
 }
 
-#[query]
-fn get_user_purchases(user: Principal) -> Vec<Purchase> {
-    PURCHASES.with(|purchases| {
-        purchases.borrow().values()
-            .filter(|purchase| purchase.buyer == user)
-            .cloned()
-            .collect()
-    })
+#[update]
+fn transfer_admin(new_admin: Principal) -> Result<(), TicketingError> {
+    require_admin(ic_cdk::caller())?;
+    ADMIN.with(|admin| *admin.borrow_mut() = Some(new_admin));
+    record_audit(ic_cdk::caller(), format!("Transferred admin to {}", new_admin), None);
+    Ok(())
 }
 
+// Admin-only since it exposes platform revenue
 #[query]
-fn verify_ticket(ticket_id: u64, verification_code: String) -> Result<Ticket, TicketingError> {
-    TICKETS.with(|tickets| {
-        let ticket = tickets.borrow().get(&ticket_id)
-            .cloned()
-            .ok_or(TicketingError::TicketNotFound)?;
+fn get_platform_stats() -> Result<PlatformStats, TicketingError> {
+    require_admin(ic_cdk::caller())?;
 
-        if ticket.verification_code != verification_code {
-            return Err(TicketingError::InvalidVerificationCode);
+    let (total_events, active_events, event_tokens) = EVENTS.with(|events| {
+        let events = events.borrow();
+        let active = events.values().filter(|event| event.is_active).count();
+        let tokens: HashMap<u64, PaymentToken> = events.values()
+            .map(|event| (event.id, event.payment_token))
+            .collect();
+        (events.len() as u64, active as u64, tokens)
+    });
+
+    // Free RSVPs aren't sales
+    let mut revenue: BTreeMap<PaymentToken, u64> = BTreeMap::new();
+    let mut free_purchases: BTreeSet<u64> = BTreeSet::new();
+    PURCHASES.with(|purchases| {
+        for purchase in purchases.borrow().values() {
+            if purchase.is_free {
+                free_purchases.insert(purchase.id);
+                continue;
+            }
+            let token = event_tokens.get(&purchase.event_id).copied().unwrap_or_default();
+            *revenue.entry(token).or_insert(0) += purchase.total_amount;
         }
+    });
+
+    let (tickets_sold, tickets_used) = TICKETS.with(|tickets| {
+        let tickets = tickets.borrow();
+        let sold = tickets.values().filter(|ticket| !free_purchases.contains(&ticket.purchase_id)).count();
+        let used = tickets.values().filter(|ticket| ticket.is_used).count();
+        (sold as u64, used as u64)
+    });
 
-        Ok(ticket)
+    Ok(PlatformStats {
+        total_events,
+        active_events,
+        tickets_sold,
+        tickets_used,
+        revenue: revenue.into_iter().collect(),
+        registered_users: USER_PROFILES.with(|profiles| profiles.borrow().len() as u64),
     })
 }
 
+// Recounts an event's inventory from its outstanding tickets and reservation holds and
+// resets the available counts to match, returning the corrected `available_tickets`.
+// Purchases still waiting on the ledger hold inventory that can't be seen here, so pause
+// the platform first.
 #[update]
-fn use_ticket(ticket_id: u64, verification_code: String) -> Result<(), TicketingError> {
-    let caller = ic_cdk::caller();
-    
-    TICKETS.with(|tickets| {
-        let mut tickets = tickets.borrow_mut();
-        let ticket = tickets.get_mut(&ticket_id)
-            .ok_or(TicketingError::TicketNotFound)?;
+fn reconcile_event_inventory(event_id: u64) -> Result<u32, TicketingError> {
+    require_admin(ic_cdk::caller())?;
 
-        if ticket.verification_code != verification_code {
-            return Err(TicketingError::InvalidVerificationCode);
+    let mut outstanding: HashMap<String, u32> = HashMap::new();
+    TICKETS.with(|tickets| {
+        for ticket in tickets.borrow().values().filter(|ticket| ticket.event_id == event_id) {
+            *outstanding.entry(ticket.tier.clone()).or_insert(0) += 1;
+        }
+    });
+    RESERVATIONS.with(|reservations| {
+        for reservation in reservations.borrow().values().filter(|reservation| reservation.hold.event_id == event_id) {
+            *outstanding.entry(reservation.hold.tier_name.clone()).or_insert(0) += reservation.hold.quantity;
         }
+    });
 
-        if ticket.is_used {
-            return Err(TicketingError::AlreadyUsed);
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = events.get_mut(&event_id)
+            .ok_or(TicketingError::EventNotFound)?;
+
+        let before = event.available_tickets;
+        for tier in &mut event.tiers {
+            let taken = outstanding.get(&tier.name).copied().unwrap_or(0);
+            tier.available = tier.total.saturating_sub(taken);
         }
+        event.available_tickets = event.tiers.iter().map(|tier| tier.available).sum();
+        event.reserved_tickets = event.reserved_tickets.min(event.available_tickets);
 
-        // Check if caller is authorized (event organizer or venue staff)
-        let event = EVENTS.with(|events| {
-            events.borrow().get(&ticket.event_id).cloned()
-        }).ok_or(TicketingError::EventNotFound)?;
+        record_audit(
+            ic_cdk::caller(),
+            format!("Reconciled inventory: {} -> {} available", before, event.available_tickets),
+            Some(event_id),
+        );
+        ic_cdk::println!(
+            "Reconciled inventory of event {}: {} -> {} available",
+            event_id, before, event.available_tickets
+        );
+        Ok(event.available_tickets)
+    })
+}
 
-        if caller != event.organizer {
+// Newest first. An event's log is open to its organizer and FullAdmin co-organizers;
+// the platform-wide log (event_id None) only to the admin.
+#[query]
+fn get_audit_log(event_id: Option<u64>, offset: u64, limit: u64) -> Result<(Vec<AuditEntry>, u64), TicketingError> {
+    let caller = ic_cdk::caller();
+
+    let is_admin = require_admin(caller).is_ok();
+    if let Some(event_id) = event_id {
+        if !is_admin && !get_event(event_id)?.is_event_admin(caller) {
             return Err(TicketingError::Unauthorized);
         }
+    } else if !is_admin {
+        return Err(TicketingError::Unauthorized);
+    }
 
-        ticket.is_used = true;
-        Ok(())
+    let limit = limit.min(MAX_PAGE_SIZE) as usize;
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let matching = || log.iter()
+            .rev()
+            .filter(|entry| event_id.is_none() || entry.event_id == event_id);
+        let page = matching()
+            .skip(offset as usize)
+            .take(limit)
+            .cloned()
+            .collect();
+        Ok((page, matching().count() as u64))
     })
 }
 
 #[query]
-fn get_event_statistics(event_id: u64) -> Result<(u32, u32, u64), TicketingError> {
-    let event = get_event(event_id)?;
-    let sold_tickets = event.total_tickets - event.available_tickets;
-    let total_revenue = (sold_tickets as u64) * event.price_icp;
-    
-    Ok((sold_tickets, event.available_tickets, total_revenue))
+fn get_platform_fee() -> u16 {
+    PLATFORM_FEE_BPS.with(|fee| *fee.borrow())
 }
 
 #[update]
-fn deactivate_event(event_id: u64) -> Result<(), TicketingError> {
+fn set_platform_fee(fee_bps: u16) -> Result<(), TicketingError> {
+    require_admin(ic_cdk::caller())?;
+
+    if fee_bps > MAX_PLATFORM_FEE_BPS {
+        return Err(TicketingError::InvalidFee);
+    }
+
+    PLATFORM_FEE_BPS.with(|fee| *fee.borrow_mut() = fee_bps);
+    record_audit(ic_cdk::caller(), format!("Set platform fee to {} bps", fee_bps), None);
+    Ok(())
+}
+
+#[query]
+fn is_platform_paused() -> bool {
+    PLATFORM_PAUSED.with(|paused| *paused.borrow())
+}
+
+#[update]
+fn set_platform_paused(paused: bool) -> Result<(), TicketingError> {
+    require_admin(ic_cdk::caller())?;
+    PLATFORM_PAUSED.with(|current| *current.borrow_mut() = paused);
+    record_audit(ic_cdk::caller(), if paused { "Paused the platform" } else { "Unpaused the platform" }, None);
+    Ok(())
+}
+
+#[query]
+fn get_no_show_penalty() -> u32 {
+    NO_SHOW_PENALTY.with(|penalty| *penalty.borrow())
+}
+
+// Applies to events that finish after the change; 0 turns the penalty off
+#[update]
+fn set_no_show_penalty(penalty: u32) -> Result<(), TicketingError> {
+    require_admin(ic_cdk::caller())?;
+    NO_SHOW_PENALTY.with(|current| *current.borrow_mut() = penalty);
+    record_audit(ic_cdk::caller(), format!("Set no-show penalty to {}", penalty), None);
+    Ok(())
+}
+
+// Returns the rate limit window (in nanoseconds) and the calls allowed within it
+#[query]
+fn get_rate_limit() -> (u64, u32) {
+    (
+        RATE_LIMIT_WINDOW.with(|window| *window.borrow()),
+        RATE_LIMIT_MAX_CALLS.with(|max_calls| *max_calls.borrow()),
+    )
+}
+
+#[update]
+fn set_rate_limit(window: u64, max_calls: u32) -> Result<(), TicketingError> {
+    require_admin(ic_cdk::caller())?;
+
+    RATE_LIMIT_WINDOW.with(|current| *current.borrow_mut() = window);
+    RATE_LIMIT_MAX_CALLS.with(|current| *current.borrow_mut() = max_calls);
+    record_audit(ic_cdk::caller(), format!("Set rate limit to {} calls per {} ns", max_calls, window), None);
+    Ok(())
+}
+
+// Pays the platform fees accumulated in `token` (ICP by default) out to the admin
+#[update]
+async fn withdraw_platform_fees(token: Option<PaymentToken>) -> Result<u64, TicketingError> {
     let caller = ic_cdk::caller();
-    
-    EVENTS.with(|events| {
-        let mut events = events.borrow_mut();
-        let event = events.get_mut(&event_id)
-            .ok_or(TicketingError::EventNotFound)?;
+    require_admin(caller)?;
 
-        if event.organizer != caller {
-            return Err(TicketingError::Unauthorized);
-        }
+    let token = token.unwrap_or_default();
+    let amount = PLATFORM_FEE_BALANCE.with(|balance| {
+        balance.borrow_mut().remove(&token).unwrap_or(0)
+    });
 
-        event.is_active = false;
-        Ok(())
+    if let Err(err) = send_payment(token, caller, amount).await {
+        PLATFORM_FEE_BALANCE.with(|balance| {
+            *balance.borrow_mut().entry(token).or_insert(0) += amount;
+        });
+        return Err(err);
+    }
+
+    Ok(amount)
+}
+
+#[query]
+fn get_organizer_balance(organizer: Principal, token: Option<PaymentToken>) -> u64 {
+    let token = token.unwrap_or_default();
+    ORGANIZER_BALANCES.with(|balances| {
+        balances.borrow().get(&(organizer, token)).copied().unwrap_or(0)
     })
 }
 
+// Pays `amount` of the caller's sales proceeds in `token` (ICP by default) out to them
+#[update]
+async fn withdraw_organizer_balance(amount: u64, token: Option<PaymentToken>) -> Result<u64, TicketingError> {
+    let caller = ic_cdk::caller();
+    let token = token.unwrap_or_default();
+
+    // Debit up front so concurrent withdrawals can't overdraw the balance
+    debit_organizer(caller, token, amount)?;
+
+    if let Err(err) = send_payment(token, caller, amount).await {
+        credit_organizer(caller, token, amount);
+        return Err(err);
+    }
+
+    Ok(amount)
+}
+
 #[query]
 fn get_user_profile(user: Principal) -> UserProfile {
     get_or_create_user_profile(user)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundle_token_must_match_every_event() {
+        assert!(check_bundle_token(PaymentToken::ICP, &[PaymentToken::ICP, PaymentToken::ICP]).is_ok());
+        assert!(matches!(
+            check_bundle_token(PaymentToken::ICP, &[PaymentToken::ICP, PaymentToken::CkUSDC]),
+            Err(TicketingError::InvalidBundle)
+        ));
+    }
+
+    fn purchase_request(quantity: u32) -> PurchaseRequest {
+        PurchaseRequest {
+            event_id: 1,
+            quantity,
+            tier_name: None,
+            seats: Vec::new(),
+            promo_code: None,
+            allow_partial: false,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn zero_quantity_purchase_is_rejected() {
+        let result = validate_purchase(Principal::anonymous(), &purchase_request(0), 0);
+        assert!(matches!(result, Err(TicketingError::InvalidQuantity)));
+    }
+
+    #[test]
+    fn total_amount_handles_extreme_quantities() {
+        assert!(matches!(calculate_total_amount(100, 0), Ok(0)));
+        assert!(matches!(calculate_total_amount(1, u32::MAX), Ok(total) if total == u32::MAX as u64));
+        assert!(matches!(calculate_total_amount(u64::MAX / 2, 2), Ok(total) if total == u64::MAX - 1));
+        assert!(matches!(calculate_total_amount(u64::MAX, 2), Err(TicketingError::AmountOverflow)));
+        assert!(matches!(calculate_total_amount(u64::MAX / 2, u32::MAX), Err(TicketingError::AmountOverflow)));
+    }
+
+    #[test]
+    fn maximum_quantity_purchase_is_rejected() {
+        let buyer = Principal::from_slice(&[2]);
+        let event = sample_event(1, Principal::from_slice(&[1]));
+        EVENTS.with(|events| events.borrow_mut().insert(1, event.clone()));
+
+        let result = validate_purchase(buyer, &purchase_request(u32::MAX), 100);
+        assert!(matches!(result, Err(TicketingError::AboveMaxPurchase)));
+
+        // Even with no per-order bound it can't get past the inventory
+        let unbounded = Event {
+            max_tickets_per_purchase: u32::MAX,
+            max_tickets_per_user: u32::MAX,
+            ..event
+        };
+        EVENTS.with(|events| events.borrow_mut().insert(1, unbounded));
+        let result = validate_purchase(buyer, &purchase_request(u32::MAX), 100);
+        assert!(matches!(result, Err(TicketingError::InsufficientTickets)));
+    }
+
+    #[test]
+    fn sequential_tickets_get_unrelated_codes() {
+        VERIFICATION_SEED.with(|seed| *seed.borrow_mut() = vec![7; 32]);
+
+        let first = generate_verification_code(41);
+        let second = generate_verification_code(42);
+        let as_number = |code: &str| u64::from_str_radix(&code.replace('-', ""), 16).unwrap();
+
+        // Consecutive ids don't give consecutive codes, and roughly half the bits differ
+        assert_ne!(as_number(&second), as_number(&first).wrapping_add(1));
+        let differing_bits = (as_number(&first) ^ as_number(&second)).count_ones();
+        assert!(differing_bits >= 16, "codes too similar: {} vs {}", first, second);
+
+        // Regenerating a code for the same ticket never repeats it
+        assert_ne!(generate_verification_code(41), first);
+    }
+
+    #[test]
+    fn inverted_time_windows_are_rejected() {
+        let hour = 60 * 60 * 1_000_000_000;
+        let now = 100 * hour;
+        assert!(validate_time_window(now + 10 * hour, now + hour, now + 2 * hour, now).is_ok());
+
+        let invalid = [
+            (now + 10 * hour, now + 2 * hour, now + hour), // Sale ends before it starts
+            (now + 10 * hour, now + hour, now + hour), // Sale closes the moment it opens
+            (now + 10 * hour, now + hour, now + 11 * hour), // Sale runs past the event
+            (now - hour, now - 3 * hour, now - 2 * hour), // Event already happened
+            (now, now - 2 * hour, now - hour), // Event is happening right now
+        ];
+        for (date, sale_start_time, sale_end_time) in invalid {
+            assert!(matches!(
+                validate_time_window(date, sale_start_time, sale_end_time, now),
+                Err(TicketingError::InvalidTimeWindow)
+            ));
+        }
+    }
+
+    #[test]
+    fn rate_limit_slides_with_time() {
+        let second = 1_000_000_000;
+        RATE_LIMIT_WINDOW.with(|window| *window.borrow_mut() = 60 * second);
+        RATE_LIMIT_MAX_CALLS.with(|max_calls| *max_calls.borrow_mut() = 3);
+        let caller = Principal::from_slice(&[1]);
+
+        // Rapid calls use up the window
+        for call in 0..3 {
+            assert!(check_rate_limit(caller, call * second).is_ok());
+        }
+        assert!(matches!(check_rate_limit(caller, 3 * second), Err(TicketingError::RateLimited)));
+
+        // Other callers have their own budget
+        assert!(check_rate_limit(Principal::from_slice(&[2]), 3 * second).is_ok());
+
+        // Once the first call ages out there's room for exactly one more
+        assert!(check_rate_limit(caller, 60 * second).is_ok());
+        assert!(matches!(check_rate_limit(caller, 60 * second), Err(TicketingError::RateLimited)));
+
+        // A quiet minute resets it entirely
+        for call in 0..3 {
+            assert!(check_rate_limit(caller, (200 + call) * second).is_ok());
+        }
+    }
+
+    fn sample_event(id: u64, organizer: Principal) -> Event {
+        Event {
+            id,
+            name: "Launch Party".to_string(),
+            description: String::new(),
+            venue: "Main Hall".to_string(),
+            category: DEFAULT_CATEGORY.to_string(),
+            image_url: String::new(),
+            metadata_url: None,
+            notify_canister: None,
+            series_id: None,
+            date: 1_000,
+            previous_date: None,
+            rescheduled_at: None,
+            total_tickets: 10,
+            available_tickets: 10,
+            reserved_tickets: 0,
+            low_stock_threshold: None,
+            price_icp: 100,
+            price_schedule: Vec::new(),
+            display_price: None,
+            payment_token: PaymentToken::ICP,
+            tiers: vec![TicketTier { name: "General".to_string(), price_icp: 100, total: 10, available: 10 }],
+            seat_map: Vec::new(),
+            seat_numbering: SeatNumbering::Sequential,
+            custom_fields: Vec::new(),
+            organizer,
+            staff: Vec::new(),
+            co_organizers: Vec::new(),
+            max_tickets_per_user: 2,
+            min_tickets_per_purchase: 1,
+            max_tickets_per_purchase: 10,
+            presale_start_time: None,
+            sale_start_time: 0,
+            sale_end_time: 500,
+            auto_extend: None,
+            auto_extend_max: None,
+            auto_extended: 0,
+            refund_deadline: 500,
+            check_in_window: None,
+            requires_verified_buyers: false,
+            min_reputation: None,
+            allowlist: None,
+            transferable: true,
+            resale_cap_bps: None,
+            refunds_require_approval: false,
+            is_active: true,
+            cancelled: false,
+            completed: false,
+            no_shows_penalized: false,
+            archived: false,
+            review_count: 0,
+            average_rating: None,
+            status: EventStatus::OnSale,
+        }
+    }
+
+    fn sample_ticket(id: u64, event_id: u64, owner: Principal) -> Ticket {
+        Ticket {
+            id,
+            event_id,
+            purchase_id: 1,
+            owner,
+            seat_number: id.to_string(),
+            tier: "General".to_string(),
+            purchase_time: 0,
+            is_used: false,
+            used_at: None,
+            used_by: None,
+            verification_code: format!("CODE-{}", id),
+            needs_reacknowledgement: false,
+            is_comp: false,
+            price_paid: 100,
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn transferring_away_does_not_free_up_the_limit() {
+        VERIFICATION_SEED.with(|seed| *seed.borrow_mut() = vec![7; 32]);
+        let buyer = Principal::from_slice(&[1]);
+        let friend = Principal::from_slice(&[2]);
+        let event_id = 1;
+        EVENTS.with(|events| events.borrow_mut().insert(event_id, sample_event(event_id, Principal::from_slice(&[9]))));
+
+        // Buy up to a limit of two, as complete_purchase records it
+        for ticket_id in [1, 2] {
+            TICKETS.with(|tickets| tickets.borrow_mut().insert(ticket_id, sample_ticket(ticket_id, event_id, buyer)));
+            adjust_owned_tickets(buyer, event_id, 1);
+        }
+        USER_EVENT_PURCHASES.with(|purchases| purchases.borrow_mut().insert((buyer, event_id), 2));
+
+        for ticket_id in [1, 2] {
+            reassign_ticket(ticket_id, friend).unwrap();
+        }
+
+        // The buyer holds nothing but has still bought their two, and the friend's
+        // received tickets count against them too
+        assert_eq!(tickets_counted_against_limit(buyer, event_id), 2);
+        assert_eq!(tickets_counted_against_limit(friend, event_id), 2);
+        assert!(matches!(
+            validate_purchase(buyer, &purchase_request(1), 100),
+            Err(TicketingError::ExceedsMaxTicketsPerUser)
+        ));
+        assert!(validate_purchase(Principal::from_slice(&[3]), &purchase_request(1), 100).is_ok());
+    }
+
+    #[test]
+    fn seat_block_is_claimed_all_or_nothing() {
+        let buyer = Principal::from_slice(&[1]);
+        let event_id = 1;
+        let mut event = sample_event(event_id, Principal::from_slice(&[9]));
+        event.seat_map = vec!["A1".to_string(), "A2".to_string(), "A3".to_string()];
+        event.max_tickets_per_user = 10;
+        EVENTS.with(|events| events.borrow_mut().insert(event_id, event));
+        take_seats(event_id, &["A2".to_string()]);
+
+        let request = PurchaseRequest {
+            seats: vec!["A1".to_string(), "A2".to_string(), "A3".to_string()],
+            ..purchase_request(3)
+        };
+        assert!(matches!(place_hold(buyer, request, 100), Err(TicketingError::SeatUnavailable)));
+        let block = ["A1".to_string(), "A2".to_string(), "A3".to_string()];
+        assert!(matches!(claim_seats(event_id, &block), Err(TicketingError::SeatUnavailable)));
+
+        // Neither neighbour of the taken seat was claimed and nothing was sold
+        let taken = TAKEN_SEATS.with(|taken| taken.borrow().get(&event_id).cloned().unwrap_or_default());
+        assert_eq!(taken, BTreeSet::from(["A2".to_string()]));
+        assert_eq!(EVENTS.with(|events| events.borrow()[&event_id].available_tickets), 10);
+        assert_eq!(tickets_counted_against_limit(buyer, event_id), 0);
+        assert!(TICKETS.with(|tickets| tickets.borrow().is_empty()));
+
+        assert!(claim_seats(event_id, &["A1".to_string(), "A3".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn availability_never_exceeds_total() {
+        let mut event = sample_event(1, Principal::from_slice(&[9]));
+
+        // Returning a ticket that was never sold is refused instead of inflating stock
+        assert!(matches!(event.adjust_inventory("General", 1), Err(TicketingError::InventoryInconsistent)));
+        assert!(matches!(event.adjust_inventory("General", -11), Err(TicketingError::InventoryInconsistent)));
+        assert_eq!((event.available_tickets, event.tiers[0].available), (10, 10));
+
+        event.adjust_inventory("General", -3).unwrap();
+        event.adjust_inventory("General", 3).unwrap();
+        assert!(event.adjust_inventory("General", 1).is_err());
+        assert_eq!((event.available_tickets, event.tiers[0].available), (10, 10));
+
+        // A tier can't overflow on its own either, and a failure leaves the event untouched
+        event.available_tickets = 5;
+        assert!(event.adjust_inventory("General", 1).is_err());
+        assert_eq!((event.available_tickets, event.tiers[0].available), (5, 10));
+    }
+
+    #[test]
+    fn anonymous_caller_is_rejected() {
+        assert!(matches!(require_authenticated(Principal::anonymous()), Err(TicketingError::AnonymousCaller)));
+        assert!(require_authenticated(Principal::from_slice(&[1])).is_ok());
+    }
+
+    #[test]
+    fn overlapping_bookings_at_a_venue_are_rejected() {
+        let hour = 60 * 60 * 1_000_000_000;
+        let organizer = Principal::from_slice(&[9]);
+        let mut booked = sample_event(1, organizer);
+        booked.date = 100 * hour;
+        booked.is_active = false; // A paused sale still holds the venue
+        EVENTS.with(|events| events.borrow_mut().insert(1, booked.clone()));
+
+        // Same venue, however it's typed, within the buffer either side
+        for date in [100 * hour, 97 * hour, 103 * hour + hour - 1] {
+            assert!(matches!(
+                check_venue_available(organizer, " MAIN hall ", date),
+                Err(TicketingError::VenueDoubleBooked)
+            ));
+        }
+
+        // Back-to-back bookings exactly one buffer apart are fine
+        assert!(check_venue_available(organizer, "Main Hall", 104 * hour).is_ok());
+        assert!(check_venue_available(organizer, "Main Hall", 96 * hour).is_ok());
+
+        // Other venues and other organizers don't clash
+        assert!(check_venue_available(organizer, "Side Room", 100 * hour).is_ok());
+        assert!(check_venue_available(Principal::from_slice(&[8]), "Main Hall", 100 * hour).is_ok());
+
+        // Cancelling frees the slot
+        EVENTS.with(|events| events.borrow_mut().insert(1, Event { cancelled: true, ..booked }));
+        assert!(check_venue_available(organizer, "Main Hall", 100 * hour).is_ok());
+    }
+
+    #[test]
+    fn series_occurrences_must_not_overlap_each_other() {
+        let hour = 60 * 60 * 1_000_000_000;
+        assert!(check_series_dates_apart(&[200 * hour, 100 * hour, 104 * hour]).is_ok());
+        assert!(matches!(
+            check_series_dates_apart(&[200 * hour, 100 * hour, 102 * hour]),
+            Err(TicketingError::VenueDoubleBooked)
+        ));
+        assert!(matches!(
+            check_series_dates_apart(&[100 * hour, 100 * hour]),
+            Err(TicketingError::VenueDoubleBooked)
+        ));
+    }
+
+    #[test]
+    fn purchase_caller_checks_cover_every_path() {
+        let buyer = Principal::from_slice(&[1]);
+        assert!(matches!(check_purchase_caller(Principal::anonymous(), 0), Err(TicketingError::AnonymousCaller)));
+        assert!(check_purchase_caller(buyer, 0).is_ok());
+
+        PLATFORM_PAUSED.with(|paused| *paused.borrow_mut() = true);
+        assert!(matches!(check_purchase_caller(buyer, 0), Err(TicketingError::PlatformPaused)));
+    }
+
+    #[test]
+    fn refunds_follow_what_each_ticket_cost() {
+        let buyer = Principal::from_slice(&[1]);
+        // 3 tickets bought for 1_000 with a 5% fee; tier pricing made them uneven
+        PURCHASES.with(|purchases| purchases.borrow_mut().insert(1, Purchase {
+            id: 1,
+            event_id: 1,
+            buyer,
+            quantity: 3,
+            requested_quantity: 3,
+            total_amount: 1_000,
+            fee_amount: 50,
+            promo_code: None,
+            purchase_time: 0,
+            ticket_ids: vec![1, 2, 3],
+            chargeback_flagged: false,
+            receipt_hash: String::new(),
+            split_from: None,
+            superseded_receipt_hashes: Vec::new(),
+            is_free: false,
+        }));
+
+        let tickets: Vec<Ticket> = [(1, 500), (2, 300), (3, 200)].into_iter()
+            .map(|(id, price_paid)| Ticket { price_paid, ..sample_ticket(id, 1, buyer) })
+            .collect();
+        let refunds: Vec<u64> = tickets.iter().map(refund_amount).collect();
+        assert_eq!(refunds, vec![475, 285, 190]);
+
+        // Rounding never refunds more than the organizer got
+        let odd = Ticket { price_paid: 333, ..sample_ticket(4, 1, buyer) };
+        assert_eq!(refund_amount(&odd), 316);
+        assert!(refunds.iter().sum::<u64>() <= 1_000 - 50);
+    }
+
+    #[test]
+    fn auto_extension_stops_at_its_maximum() {
+        let hour = 60 * 60 * 1_000_000_000;
+        let mut event = sample_event(1, Principal::from_slice(&[9]));
+        event.sale_end_time = 10 * hour;
+        event.date = 100 * hour;
+        event.auto_extend = Some(2 * hour);
+        event.auto_extend_max = Some(5 * hour);
+
+        // Not due yet, then one step past the overdue time
+        assert_eq!(auto_extended_sale_end(&event, 10 * hour), None);
+        assert_eq!(auto_extended_sale_end(&event, 11 * hour), Some(12 * hour));
+
+        // Catching up on several steps is capped at the scheduled end plus the maximum
+        event.sale_end_time = 14 * hour;
+        event.auto_extended = 4 * hour;
+        assert_eq!(auto_extended_sale_end(&event, 15 * hour), Some(15 * hour));
+
+        event.sale_end_time = 15 * hour;
+        event.auto_extended = 5 * hour;
+        assert_eq!(auto_extended_sale_end(&event, 16 * hour), None);
+
+        // Without a maximum only the event date caps it
+        event.auto_extend_max = None;
+        assert_eq!(auto_extended_sale_end(&event, 99 * hour), Some(100 * hour));
+
+        // Sold-out events aren't extended
+        event.available_tickets = 0;
+        assert_eq!(auto_extended_sale_end(&event, 16 * hour), None);
+    }
+
+    fn event_input(venue: &str) -> EventInput {
+        EventInput {
+            name: "Launch Party".to_string(),
+            description: String::new(),
+            venue: venue.to_string(),
+            date: 1_000,
+            total_tickets: 10,
+            price_icp: 100,
+            max_tickets_per_user: 2,
+            presale_start_time: None,
+            sale_start_time: 100,
+            sale_end_time: 500,
+            auto_extend: None,
+            auto_extend_max: None,
+            refund_deadline: None,
+            tiers: None,
+            category: None,
+            requires_verified_buyers: None,
+            min_reputation: None,
+            seat_map: None,
+            seat_numbering: None,
+            custom_fields: None,
+            allowlist: None,
+            price_schedule: None,
+            transferable: None,
+            image_url: None,
+            metadata_url: None,
+            allow_venue_overlap: None,
+            notify_canister: None,
+        }
+    }
+
+    #[test]
+    fn seat_numbering_can_be_set_at_creation() {
+        let organizer = Principal::from_slice(&[1]);
+
+        let input = EventInput {
+            seat_numbering: Some(SeatNumbering::RowCol { rows: 2, cols: 5 }),
+            ..event_input("Hall A")
+        };
+        let event = build_event(organizer, input, 0).unwrap();
+        assert!(matches!(event.seat_numbering, SeatNumbering::RowCol { rows: 2, cols: 5 }));
+
+        // Too few seats for the tickets on sale
+        let input = EventInput {
+            seat_numbering: Some(SeatNumbering::RowCol { rows: 2, cols: 4 }),
+            ..event_input("Hall A")
+        };
+        assert!(matches!(build_event(organizer, input, 0), Err(TicketingError::InvalidSeatMap)));
+    }
+
+    #[test]
+    fn price_schedule_can_be_set_at_creation() {
+        let organizer = Principal::from_slice(&[1]);
+        let early_bird = vec![
+            PriceStep { effective_from: 100, price_icp: 80 },
+            PriceStep { effective_from: 300, price_icp: 120 },
+        ];
+
+        let input = EventInput { price_schedule: Some(early_bird.clone()), ..event_input("Hall B") };
+        let event = build_event(organizer, input, 0).unwrap();
+        assert_eq!(event.tier_price(&event.tiers[0], 200), 80);
+        assert_eq!(event.tier_price(&event.tiers[0], 400), 120);
+
+        let reversed = early_bird.into_iter().rev().collect();
+        let input = EventInput { price_schedule: Some(reversed), ..event_input("Hall B") };
+        assert!(matches!(build_event(organizer, input, 0), Err(TicketingError::InvalidPriceSchedule)));
+    }
+
+    #[test]
+    fn allowlist_can_be_set_at_creation() {
+        let organizer = Principal::from_slice(&[1]);
+        let member = Principal::from_slice(&[2]);
+
+        let input = EventInput { allowlist: Some(vec![member]), ..event_input("Hall C") };
+        let event = build_event(organizer, input, 0).unwrap();
+        assert!(event.is_invited(member));
+        assert!(!event.is_invited(Principal::from_slice(&[3])));
+    }
+
+    #[test]
+    fn transferability_can_be_set_at_creation() {
+        let organizer = Principal::from_slice(&[1]);
+
+        let event = build_event(organizer, event_input("Hall D"), 0).unwrap();
+        assert!(event.transferable);
+
+        let input = EventInput { transferable: Some(false), ..event_input("Hall D") };
+        let event = build_event(organizer, input, 0).unwrap();
+        assert!(!event.transferable);
+    }
+
+    #[test]
+    fn attendee_details_are_only_shown_to_event_admins() {
+        let organizer = Principal::from_slice(&[1]);
+        let owner = Principal::from_slice(&[2]);
+        let scanner = Principal::from_slice(&[3]);
+        let event = Event { staff: vec![scanner], ..sample_event(1, organizer) };
+
+        let mut ticket = sample_ticket(1, 1, owner);
+        ticket.metadata.insert("dietary".to_string(), "vegan".to_string());
+
+        let seen_by_owner = redacted_by(owner, Some(&event), ticket.clone());
+        assert!(!seen_by_owner.verification_code.is_empty() && !seen_by_owner.metadata.is_empty());
+
+        let seen_by_organizer = redacted_by(organizer, Some(&event), ticket.clone());
+        assert!(!seen_by_organizer.verification_code.is_empty() && !seen_by_organizer.metadata.is_empty());
+
+        // Door staff need the code to check people in, but not their answers
+        let seen_by_scanner = redacted_by(scanner, Some(&event), ticket);
+        assert!(!seen_by_scanner.verification_code.is_empty());
+        assert!(seen_by_scanner.metadata.is_empty());
+    }
+
+    #[test]
+    fn resale_is_capped_at_what_the_ticket_cost() {
+        let organizer = Principal::from_slice(&[1]);
+        let holder = Principal::from_slice(&[2]);
+        let mut event = sample_event(1, organizer);
+
+        // Bought at an early-bird or promo price below the tier's 100
+        let discounted = Ticket { price_paid: 80, ..sample_ticket(1, 1, holder) };
+        assert_eq!(event.resale_cap(&discounted), 80);
+
+        event.resale_cap_bps = Some(12_500);
+        assert_eq!(event.resale_cap(&discounted), 100);
+        assert_eq!(event.resale_cap(&Ticket { price_paid: u64::MAX, ..discounted }), u64::MAX);
+    }
+}
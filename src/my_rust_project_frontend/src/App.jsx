@@ -47,6 +47,27 @@ const idlFactory = ({ IDL }) => {
     is_active: IDL.Bool,
   });
 
+  const PaymentToken = IDL.Variant({ ICP: IDL.Null, CkUSDC: IDL.Null });
+
+  const TicketTier = IDL.Record({
+    name: IDL.Text,
+    price_icp: IDL.Nat64,
+    total: IDL.Nat32,
+    available: IDL.Nat32,
+  });
+
+  const EventMedia = IDL.Record({
+    image_url: IDL.Opt(IDL.Text),
+    metadata_url: IDL.Opt(IDL.Text),
+  });
+
+  const TierStatistics = IDL.Record({
+    name: IDL.Text,
+    sold: IDL.Nat32,
+    available: IDL.Nat32,
+    revenue: IDL.Nat64,
+  });
+
   const TicketRec = IDL.Record({
     id: IDL.Nat64,
     event_id: IDL.Nat64,
@@ -86,6 +107,74 @@ const idlFactory = ({ IDL }) => {
     TicketNotFound: IDL.Null,
     AlreadyUsed: IDL.Null,
     InvalidVerificationCode: IDL.Null,
+    PaymentFailed: IDL.Null,
+    RefundWindowClosed: IDL.Null,
+    SaleAlreadyStarted: IDL.Null,
+    TierNotFound: IDL.Null,
+    InvalidTiers: IDL.Null,
+    AmountOverflow: IDL.Null,
+    InvalidQuantity: IDL.Null,
+    ResalePriceTooHigh: IDL.Null,
+    TicketListedForResale: IDL.Null,
+    NotListedForResale: IDL.Null,
+    EventAlreadyHappened: IDL.Null,
+    AlreadyOnWaitlist: IDL.Null,
+    NotOnWaitlist: IDL.Null,
+    EventCancelled: IDL.Null,
+    PurchaseNotFound: IDL.Null,
+    BuyerNotVerified: IDL.Null,
+    InvalidFee: IDL.Null,
+    SeatUnavailable: IDL.Null,
+    InvalidSeatMap: IDL.Null,
+    InvalidUrl: IDL.Null,
+    InvalidTimeWindow: IDL.Null,
+    PromoCodeInvalid: IDL.Null,
+    ReservationNotFound: IDL.Null,
+    ReservationExpired: IDL.Null,
+    RateLimited: IDL.Null,
+    PlatformPaused: IDL.Null,
+    EventNotFinished: IDL.Null,
+    InvalidRating: IDL.Null,
+    ReviewTooLong: IDL.Null,
+    AlreadyReviewed: IDL.Null,
+    NotAttended: IDL.Null,
+    ReservedPoolExhausted: IDL.Null,
+    EventNotFree: IDL.Null,
+    AlreadyClaimed: IDL.Null,
+    WrongEvent: IDL.Null,
+    BelowMinPurchase: IDL.Null,
+    AboveMaxPurchase: IDL.Null,
+    InvalidIdempotencyKey: IDL.Null,
+    PurchaseInProgress: IDL.Null,
+    InsufficientBalance: IDL.Null,
+    RefundApprovalRequired: IDL.Null,
+    RefundRequestNotFound: IDL.Null,
+    RefundRequestResolved: IDL.Null,
+    RefundAlreadyRequested: IDL.Null,
+    ReasonTooLong: IDL.Null,
+    EventEnded: IDL.Null,
+    SigningFailed: IDL.Null,
+    InvalidPriceSchedule: IDL.Null,
+    NotTransferable: IDL.Null,
+    InvalidCurrency: IDL.Null,
+    NotInvited: IDL.Null,
+    InventoryInconsistent: IDL.Null,
+    AlreadyFollowing: IDL.Null,
+    NotFollowing: IDL.Null,
+    OfferNotFound: IDL.Null,
+    OfferExpired: IDL.Null,
+    OfferAlreadyPending: IDL.Null,
+    ReputationTooLow: IDL.Null,
+    InvalidCustomFields: IDL.Null,
+    InvalidTicketMetadata: IDL.Null,
+    BundleNotFound: IDL.Null,
+    InvalidBundle: IDL.Null,
+    AnonymousCaller: IDL.Null,
+    FieldTooLong: IDL.Record({ field: IDL.Text }),
+    VenueDoubleBooked: IDL.Null,
+    TicketsStillAvailable: IDL.Null,
+    RefundInProgress: IDL.Null,
+    CompNotRefundable: IDL.Null,
   });
 
   const ResultEvent = IDL.Variant({
@@ -114,7 +203,13 @@ const idlFactory = ({ IDL }) => {
   });
 
   const ResultStats = IDL.Variant({
-    Ok: IDL.Tuple(IDL.Nat32, IDL.Nat32, IDL.Nat64),
+    Ok: IDL.Tuple(
+      IDL.Nat32,
+      IDL.Nat32,
+      IDL.Nat64,
+      IDL.Vec(TierStatistics),
+      PaymentToken
+    ),
     Err: TicketingError,
   });
 
@@ -133,11 +228,31 @@ const idlFactory = ({ IDL }) => {
         IDL.Nat32, // max_tickets_per_user
         IDL.Nat64, // sale_start_time
         IDL.Nat64, // sale_end_time
+        IDL.Opt(IDL.Nat64), // refund_deadline
+        IDL.Opt(IDL.Vec(TicketTier)), // tiers
+        IDL.Opt(IDL.Text), // category
+        IDL.Opt(IDL.Bool), // requires_verified_buyers
+        IDL.Opt(IDL.Vec(IDL.Text)), // seat_map
+        IDL.Opt(EventMedia), // media
+        IDL.Opt(IDL.Principal), // notify_canister
       ],
       [ResultEventId],
       []
     ),
-    purchase_tickets: IDL.Func([IDL.Nat64, IDL.Nat32], [ResultPurchase], []),
+    purchase_tickets: IDL.Func(
+      [
+        IDL.Nat64, // event_id
+        IDL.Nat32, // quantity
+        IDL.Opt(IDL.Text), // tier_name
+        IDL.Opt(IDL.Vec(IDL.Text)), // seats
+        IDL.Opt(IDL.Text), // promo_code
+        IDL.Opt(IDL.Text), // idempotency_key
+        IDL.Opt(IDL.Bool), // allow_partial
+        IDL.Opt(IDL.Vec(IDL.Tuple(IDL.Text, IDL.Text))), // metadata
+      ],
+      [ResultPurchase],
+      []
+    ),
     get_user_tickets: IDL.Func(
       [IDL.Principal],
       [IDL.Vec(TicketRec)],
@@ -150,7 +265,11 @@ const idlFactory = ({ IDL }) => {
     ),
     get_user_profile: IDL.Func([IDL.Principal], [UserProfile], ["query"]),
     verify_ticket: IDL.Func([IDL.Nat64, IDL.Text], [ResultTicket], ["query"]),
-    use_ticket: IDL.Func([IDL.Nat64, IDL.Text], [ResultVoid], []),
+    use_ticket: IDL.Func(
+      [IDL.Nat64, IDL.Text, IDL.Nat64], // ticket_id, code, expected_event_id
+      [ResultVoid],
+      []
+    ),
     get_event_statistics: IDL.Func([IDL.Nat64], [ResultStats], ["query"]),
     deactivate_event: IDL.Func([IDL.Nat64], [ResultVoid], []),
   });
@@ -193,6 +312,7 @@ function App() {
 
   // Verification State
   const [verificationForm, setVerificationForm] = useState({
+    eventId: "",
     ticketId: "",
     verificationCode: "",
   });
@@ -400,7 +520,14 @@ function App() {
           new Date(
             newEvent.saleEnd || new Date(Date.now() + 30 * 24 * 60 * 60 * 1000)
           ).getTime() * 1_000_000
-        )
+        ),
+        [], // refund_deadline
+        [], // tiers
+        [], // category
+        [], // requires_verified_buyers
+        [], // seat_map
+        [], // media
+        [] // notify_canister
       );
 
       if ("Ok" in result) {
@@ -438,7 +565,16 @@ function App() {
     if (!backend) return;
     setLoading(true);
     try {
-      const result = await backend.purchase_tickets(BigInt(eventId), quantity);
+      const result = await backend.purchase_tickets(
+        BigInt(eventId),
+        quantity,
+        [], // tier_name
+        [], // seats
+        [], // promo_code
+        [], // idempotency_key
+        [], // allow_partial
+        [] // metadata
+      );
       if ("Ok" in result) {
         const purchase = result.Ok;
         showMessage(
@@ -501,13 +637,14 @@ function App() {
     setLoading(false);
   };
 
-  const useTicket = async (ticketId, verificationCode) => {
+  const useTicket = async (ticketId, verificationCode, eventId) => {
     if (!backend) return;
     setLoading(true);
     try {
       const result = await backend.use_ticket(
         BigInt(ticketId),
-        verificationCode
+        verificationCode,
+        BigInt(eventId)
       );
       if ("Ok" in result) {
         showMessage("✅ Ticket marked as used successfully!", "success");
@@ -1281,6 +1418,24 @@ function App() {
 
               <div className="bg-white rounded-xl shadow-sm border p-6">
                 <div className="space-y-4">
+                  <div>
+                    <label className="block text-sm font-medium text-gray-700 mb-1">
+                      Event ID
+                    </label>
+                    <input
+                      type="number"
+                      placeholder="Event being checked in"
+                      className="w-full border border-gray-300 p-3 rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500"
+                      value={verificationForm.eventId}
+                      onChange={(e) =>
+                        setVerificationForm({
+                          ...verificationForm,
+                          eventId: e.target.value,
+                        })
+                      }
+                    />
+                  </div>
+
                   <div>
                     <label className="block text-sm font-medium text-gray-700 mb-1">
                       Ticket ID
@@ -1340,13 +1495,15 @@ function App() {
                       onClick={() =>
                         useTicket(
                           verificationForm.ticketId,
-                          verificationForm.verificationCode
+                          verificationForm.verificationCode,
+                          verificationForm.eventId
                         )
                       }
                       disabled={
                         loading ||
                         !verificationForm.ticketId ||
-                        !verificationForm.verificationCode
+                        !verificationForm.verificationCode ||
+                        !verificationForm.eventId
                       }
                       className="flex-1 bg-red-600 hover:bg-red-700 text-white px-6 py-3 rounded-lg font-medium transition-colors disabled:bg-red-400 flex items-center justify-center gap-2"
                     >